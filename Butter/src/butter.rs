@@ -1,5 +1,6 @@
 mod lexer;
 mod parser;
+mod checker;
 mod transpiler;
 mod compiletask;
 mod cli;
@@ -8,6 +9,7 @@ use std::{env, fs};
 use std::path::{Path, PathBuf};
 use lexer::lex;
 use parser::parse_tokens;
+use checker::check_program;
 use transpiler::transpile;
 use cli::parse_termargs;
 use compiletask:: {compiletobinary, runbinary};
@@ -28,13 +30,15 @@ fn main() {
     let (cmd, filename, olevel) = parse_termargs(env::args().collect());
     let source = load_source_with_imports(&filename);
     let tokens = lex(&source);
-    let program = parse_tokens(tokens);
+    let mut program = parse_tokens(tokens);
+    check_program(&mut program);
     let stem = file_stem_or_exit(&filename);
+    let libraries = program.libraries.clone();
     transpile(program, stem);
     match cmd.as_str() {
-        "build" => compiletobinary(stem, olevel),
+        "build" => compiletobinary(stem, olevel, &libraries),
         "run" => {
-            compiletobinary(stem, olevel);
+            compiletobinary(stem, olevel, &libraries);
             runbinary(stem);
         }
         _ => panic!("wait WTF how"),
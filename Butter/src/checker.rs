@@ -0,0 +1,2747 @@
+use crate::parser::{
+    Program, Stmt, Expr, InfixOp, PrefixOp, Type, Block, BlockOrIf, Receiver, TraitMethod, Variant,
+    ClosureInfo, InterpPart,
+};
+
+use crate::lexer::{lex, Align, FormatSpec};
+use crate::parser::parse_tokens;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+struct FuncSig {
+    params: Vec<Type>,
+    ret: Type,
+}
+
+#[derive(Debug, Clone)]
+struct MethodSig {
+    receiver: Receiver,
+    params: Vec<Type>,
+    ret: Type,
+}
+
+#[derive(Debug, Clone)]
+struct Var {
+    ty: Type,
+    mutable: bool,
+    // known at compile time, set for consts
+    value: Option<ConstVal>,
+}
+
+// the value of a constant expression
+#[derive(Debug, Clone, PartialEq)]
+enum ConstVal {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Char(char),
+}
+
+// a closure whose body is being checked
+struct ClosureFrame {
+    // scopes below this index belong to the enclosing function
+    base: usize,
+    captures: Vec<(String, Type)>,
+}
+
+// =======================
+// ======= CHECKER =======
+// =======================
+
+pub struct Checker {
+    structs: HashMap<String, Vec<(String, Type)>>,
+    funcs: HashMap<String, FuncSig>,
+    // struct name -> method name -> signature
+    methods: HashMap<String, HashMap<String, MethodSig>>,
+    traits: HashMap<String, Vec<TraitMethod>>,
+    // (struct, trait) pairs that have an `impl Trait for Struct`
+    impls: HashSet<(String, String)>,
+    // generic functions are kept untouched here and copied per call site types
+    generics: HashMap<String, Stmt>,
+    // generic name -> checked concrete copies, e.g. log -> [log__Person]
+    instances: HashMap<String, Vec<Stmt>>,
+    instantiated: HashSet<String>,
+    // type parameters (and their bounds) of the generic function being checked
+    type_params: HashMap<String, Vec<String>>,
+    // Option/Result types seen so far, handed to the transpiler via Program
+    type_instances: Vec<Type>,
+    // types print() needs a formatter for, also handed over via Program
+    display_types: Vec<Type>,
+    // runtime functions returning Option/Result (read_line, ...) that are called
+    builtins: Vec<String>,
+    // return type of the function being checked, used by `return` and `?`
+    ret_type: Option<Type>,
+    closure_stack: Vec<ClosureFrame>,
+    // checked closures, lifted to C functions by the transpiler
+    closures: Vec<Expr>,
+    // named functions used as values, wrapped in a capture-less closure once
+    func_values: HashMap<String, Expr>,
+    scopes: Vec<HashMap<String, Var>>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self {
+            structs: HashMap::new(),
+            funcs: HashMap::new(),
+            methods: HashMap::new(),
+            traits: HashMap::new(),
+            impls: HashSet::new(),
+            generics: HashMap::new(),
+            instances: HashMap::new(),
+            instantiated: HashSet::new(),
+            type_params: HashMap::new(),
+            type_instances: Vec::new(),
+            display_types: Vec::new(),
+            builtins: Vec::new(),
+            ret_type: None,
+            closure_stack: Vec::new(),
+            closures: Vec::new(),
+            func_values: HashMap::new(),
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    fn error_and_exit(msg: &str) -> ! {
+        eprintln!("\x1b[31m[BUTTER COMPILER ERROR]\x1b[0m {}", msg);
+        std::process::exit(1);
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &str, ty: Type, mutable: bool) {
+        self.scopes
+            .last_mut()
+            .expect("checker always has a global scope")
+            .insert(name.to_string(), Var { ty, mutable, value: None });
+    }
+
+    fn declare_const(&mut self, name: &str, ty: Type, value: ConstVal) {
+        self.scopes
+            .last_mut()
+            .expect("checker always has a global scope")
+            .insert(name.to_string(), Var { ty, mutable: false, value: Some(value) });
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Var> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // like lookup, but also says which scope the variable lives in
+    fn lookup_depth(&self, name: &str) -> Option<(usize, &Var)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.get(name).map(|var| (depth, var)))
+    }
+
+    // a local from outside the closure(s) we're in gets copied into their environments
+    fn note_capture(&mut self, name: &str, ty: &Type, depth: usize) {
+        if depth == 0 {
+            // globals are visible from any C function
+            return;
+        }
+        for frame in self.closure_stack.iter_mut() {
+            if depth < frame.base && !frame.captures.iter().any(|(captured, _)| captured == name) {
+                frame.captures.push((name.to_string(), ty.clone()));
+            }
+        }
+    }
+
+    fn is_captured(&self, name: &str) -> bool {
+        match (self.closure_stack.last(), self.lookup_depth(name)) {
+            (Some(frame), Some((depth, _))) => depth > 0 && depth < frame.base,
+            _ => false,
+        }
+    }
+
+    fn note_builtin(&mut self, name: String) {
+        if !self.builtins.contains(&name) {
+            self.builtins.push(name);
+        }
+    }
+
+    // main is called from C's main: it gets the command line or nothing, and
+    // returns the exit status or nothing
+    fn check_main(type_params: &[(String, Vec<String>)], params: &[(String, Type)], returntype: &Type) {
+        if !type_params.is_empty() {
+            Self::error_and_exit("'main' can't have type parameters");
+        }
+        let args = Type::Array(Box::new(Type::String));
+        match params {
+            [] => {}
+            [(_, ty)] if *ty == args => {}
+            _ => {
+                let error_msg = format!("'main' takes no parameters or a single 'args: {}'", args);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+        if !matches!(returntype, Type::Int | Type::Nil) {
+            let error_msg = format!("'main' must return Int (the exit status) or nil, not {}", returntype);
+            Self::error_and_exit(&error_msg);
+        }
+    }
+
+    // first pass: remember every struct, function and method signature so
+    // bodies can refer to things declared later in the file
+    fn collect(&mut self, program: &Program) {
+        for stmt in &program.stmts {
+            match stmt {
+                Stmt::Struct { name, fields, .. } => {
+                    for (_, ty) in fields {
+                        self.use_type(ty);
+                    }
+                    self.structs.insert(name.clone(), fields.clone());
+                }
+                Stmt::Func { name, type_params, params, returntype, .. } => {
+                    if name == "main" {
+                        Self::check_main(type_params, params, returntype);
+                    }
+                    if type_params.is_empty() {
+                        let params = params.iter().map(|(_, ty)| ty.clone()).collect();
+                        self.funcs.insert(name.clone(), FuncSig { params, ret: returntype.clone() });
+                    } else {
+                        self.generics.insert(name.clone(), stmt.clone());
+                    }
+                }
+                Stmt::Trait { name, methods } => {
+                    self.traits.insert(name.clone(), methods.clone());
+                }
+                _ => {}
+            }
+        }
+
+        // which trait, if any, each (struct, method) came from
+        let mut origins: HashMap<(String, String), Option<String>> = HashMap::new();
+        for stmt in &program.stmts {
+            if let Stmt::Impl { target, trait_name, methods } = stmt {
+                if !self.structs.contains_key(target) {
+                    let error_msg = format!("impl for unknown struct '{}'", target);
+                    Self::error_and_exit(&error_msg);
+                }
+
+                if let Some(trait_name) = trait_name {
+                    self.check_trait_impl(target, trait_name, methods);
+                    self.impls.insert((target.clone(), trait_name.clone()));
+                }
+
+                for method in methods {
+                    if let Stmt::Func { name, type_params, receiver, params, returntype, .. } = method {
+                        if !type_params.is_empty() {
+                            let error_msg = format!("generic methods are not supported yet ('{}.{}')", target, name);
+                            Self::error_and_exit(&error_msg);
+                        }
+                        let sig = MethodSig {
+                            receiver: receiver.expect("parser only accepts methods with a receiver"),
+                            params: params.iter().map(|(_, ty)| ty.clone()).collect(),
+                            ret: returntype.clone(),
+                        };
+                        let table = self.methods.entry(target.clone()).or_default();
+                        if table.insert(name.clone(), sig).is_some() {
+                            // both would be emitted as `Struct__method`
+                            let error_msg = match (&origins[&(target.clone(), name.clone())], trait_name) {
+                                (Some(first), _) | (None, Some(first)) => format!(
+                                    "method '{}' of trait '{}' clashes with another method '{}' on struct '{}', a struct's methods need distinct names",
+                                    name, first, name, target
+                                ),
+                                (None, None) => format!("duplicate method '{}' for struct '{}'", name, target),
+                            };
+                            Self::error_and_exit(&error_msg);
+                        }
+                        origins.insert((target.clone(), name.clone()), trait_name.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // the impl must provide exactly the trait's methods, with matching signatures
+    fn check_trait_impl(&self, target: &str, trait_name: &str, methods: &[Stmt]) {
+        let required = match self.traits.get(trait_name) {
+            Some(required) => required,
+            None => {
+                let error_msg = format!("impl of unknown trait '{}' for '{}'", trait_name, target);
+                Self::error_and_exit(&error_msg);
+            }
+        };
+        let self_map = HashMap::from([("Self".to_string(), Type::Custom(target.to_string()))]);
+
+        for req in required {
+            let found = methods
+                .iter()
+                .find(|method| matches!(method, Stmt::Func { name, .. } if *name == req.name));
+            let Some(Stmt::Func { receiver, params, returntype, .. }) = found else {
+                let error_msg = format!(
+                    "impl of trait '{}' for '{}' is missing method '{}'",
+                    trait_name, target, req.name
+                );
+                Self::error_and_exit(&error_msg);
+            };
+
+            let expected: Vec<Type> = req.params.iter().map(|(_, ty)| subst_type(ty, &self_map)).collect();
+            let got: Vec<Type> = params.iter().map(|(_, ty)| ty.clone()).collect();
+            if *receiver != Some(req.receiver)
+                || expected != got
+                || *returntype != subst_type(&req.returntype, &self_map)
+            {
+                let error_msg = format!(
+                    "method '{}' in impl of trait '{}' for '{}' does not match the signature declared in the trait",
+                    req.name, trait_name, target
+                );
+                Self::error_and_exit(&error_msg);
+            }
+        }
+
+        for method in methods {
+            if let Stmt::Func { name, .. } = method
+                && !required.iter().any(|req| req.name == *name)
+            {
+                let error_msg = format!("method '{}' is not a member of trait '{}'", name, trait_name);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+    }
+
+    fn check_program(&mut self, program: &mut Program) {
+        let runtime = parse_tokens(lex(RUNTIME_TYPES)).stmts;
+        for stmt in &program.stmts {
+            if let Stmt::Struct { name, .. } = stmt
+                && runtime.iter().any(|builtin| matches!(builtin, Stmt::Struct { name: taken, .. } if taken == name))
+            {
+                let error_msg = format!("'{}' is a built-in type, pick another name for this struct", name);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+        program.stmts.splice(0..0, runtime);
+
+        // `Self` inside an impl block is just the struct being implemented
+        for stmt in &mut program.stmts {
+            if let Stmt::Impl { target, methods, .. } = stmt {
+                let self_map = HashMap::from([("Self".to_string(), Type::Custom(target.clone()))]);
+                for method in methods {
+                    subst_stmt(method, &self_map);
+                }
+            }
+        }
+
+        self.collect(program);
+        if !self.funcs.contains_key("main") {
+            Self::error_and_exit("no 'fn main' found, every program starts there");
+        }
+
+        // globals first, in order, so every function can see all of them
+        for stmt in &mut program.stmts {
+            match stmt {
+                Stmt::Const { .. } => self.check_stmt(stmt),
+                Stmt::Let { .. } => {
+                    self.check_stmt(stmt);
+                    // fold it when we can, so the C global gets a static initializer
+                    if let Stmt::Let { value: Some(value), valuetype: Some(ty), .. } = stmt
+                        && let Some(folded) = self.const_eval(value, Some(ty))
+                    {
+                        *value = const_to_expr(folded, ty);
+                    }
+                }
+                Stmt::LetTuple { names, .. } => {
+                    let error_msg = format!("({}) can't be destructured at the top level, declare each global on its own", names.join(", "));
+                    Self::error_and_exit(&error_msg);
+                }
+                Stmt::Func { .. } | Stmt::Struct { .. } | Stmt::Impl { .. } | Stmt::Trait { .. } => {}
+                _ => Self::error_and_exit("only fn, struct, impl, trait, let and const are allowed at the top level"),
+            }
+        }
+
+        for stmt in &mut program.stmts {
+            match stmt {
+                Stmt::Func { type_params, .. } if !type_params.is_empty() => self.check_template(stmt),
+                Stmt::Func { .. } => self.check_func(stmt, None),
+                Stmt::Impl { target, methods, .. } => {
+                    for method in methods {
+                        self.check_func(method, Some(target));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // swap every generic function for the concrete copies its callers needed
+        let stmts = std::mem::take(&mut program.stmts);
+        for stmt in stmts {
+            match stmt {
+                Stmt::Func { ref name, ref type_params, .. } if !type_params.is_empty() => {
+                    program.stmts.extend(self.instances.remove(name).unwrap_or_default());
+                }
+                other => program.stmts.push(other),
+            }
+        }
+
+        program.type_instances = std::mem::take(&mut self.type_instances);
+        program.display_types = std::mem::take(&mut self.display_types);
+        if self.builtins.iter().any(|name| needs_libm(name)) {
+            program.libraries.push("m".to_string());
+        }
+        program.builtins = std::mem::take(&mut self.builtins);
+        program.closures = std::mem::take(&mut self.closures);
+    }
+
+    // remember composite types that need a generated C definition
+    fn use_type(&mut self, ty: &Type) {
+        if !self.type_params.is_empty() {
+            // generic templates are never emitted, only their instances
+            return;
+        }
+
+        match ty {
+            Type::Array(elem) => {
+                if **elem == Type::Nil {
+                    Self::error_and_exit("an array can't hold nil elements");
+                }
+                self.use_type(elem);
+                self.add_instance(ty);
+            }
+            Type::Option(inner) => {
+                self.use_type(inner);
+                self.add_instance(ty);
+            }
+            Type::Result(ok, err) => {
+                self.use_type(ok);
+                self.use_type(err);
+                self.add_instance(ty);
+            }
+            Type::Func(params, ret) => {
+                for param in params {
+                    self.use_type(param);
+                }
+                self.use_type(ret);
+            }
+            Type::Tuple(elems) => {
+                for elem in elems {
+                    if *elem == Type::Nil {
+                        let error_msg = format!("tuple {} can't have a nil element", ty);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    self.use_type(elem);
+                }
+                self.add_instance(ty);
+            }
+            // the C side of get(), keys(), ... comes with the map, so their
+            // types are needed as soon as the map is
+            Type::Map(key, value) => {
+                self.check_key_type(ty, key);
+                if **value == Type::Nil {
+                    Self::error_and_exit("a map can't hold nil values, use a Set for keys alone");
+                }
+                self.use_type(&Type::Array(key.clone()));
+                self.use_type(&Type::Array(value.clone()));
+                self.use_type(&Type::Option(value.clone()));
+                self.add_instance(ty);
+            }
+            Type::Set(elem) => {
+                self.check_key_type(ty, elem);
+                self.use_type(&Type::Array(elem.clone()));
+                self.add_instance(ty);
+            }
+            _ => {}
+        }
+    }
+
+    // map keys and set elements are hashed and compared by value: integers,
+    // Bool, Char, String, and structs and tuples made of those
+    fn check_key_type(&self, collection: &Type, key: &Type) {
+        let hashable = |ty: &Type| ty.is_integer() || matches!(ty, Type::Bool | Type::Char | Type::String);
+        let mut pending = vec![key.clone()];
+        let mut seen = Vec::new();
+        while let Some(ty) = pending.pop() {
+            if seen.contains(&ty) {
+                continue;
+            }
+            match &ty {
+                Type::Custom(name) if let Some(fields) = self.structs.get(name) => {
+                    pending.extend(fields.iter().map(|(_, fty)| fty.clone()));
+                }
+                Type::Tuple(elems) => pending.extend(elems.iter().cloned()),
+                other if hashable(other) => {}
+                other => {
+                    let error_msg = if *other == *key {
+                        format!("{} can't be used in {}, keys must be integers, Bool, Char, String, or structs and tuples of those", other, collection)
+                    } else {
+                        format!("{} can't be used in {}, it contains a {} and keys must be integers, Bool, Char, String, or structs and tuples of those", key, collection, other)
+                    };
+                    Self::error_and_exit(&error_msg);
+                }
+            }
+            seen.push(ty);
+        }
+    }
+
+    fn add_instance(&mut self, ty: &Type) {
+        if !self.type_instances.contains(ty) {
+            self.type_instances.push(ty.clone());
+        }
+    }
+
+    // check a generic body once against its bounds alone, so mistakes show up
+    // even if it's never called. The checked copy is thrown away.
+    fn check_template(&mut self, func: &Stmt) {
+        let mut template = func.clone();
+        if let Stmt::Func { name, type_params, .. } = &template {
+            for (_, bounds) in type_params {
+                for bound in bounds {
+                    if !self.traits.contains_key(bound) {
+                        let error_msg = format!("unknown trait '{}' used as a bound in function '{}'", bound, name);
+                        Self::error_and_exit(&error_msg);
+                    }
+                }
+            }
+            self.type_params = type_params.iter().cloned().collect();
+        }
+        self.check_func(&mut template, None);
+        self.type_params.clear();
+    }
+
+    // works out T from the argument types and returns (C function name, return type)
+    fn check_generic_call(&mut self, name: &str, args: &mut [Expr]) -> (String, Type) {
+        let Some(Stmt::Func { type_params, params, returntype, .. }) = self.generics.get(name).cloned() else {
+            unreachable!("check_generic_call on a non generic function");
+        };
+
+        if params.len() != args.len() {
+            let error_msg = format!(
+                "function '{}' expects {} argument(s), but got {}",
+                name,
+                params.len(),
+                args.len()
+            );
+            Self::error_and_exit(&error_msg);
+        }
+
+        let mut bindings = HashMap::new();
+        for ((_, param), arg) in params.iter().zip(args.iter_mut()) {
+            let Some(actual) = self.check_expr(arg) else {
+                let error_msg = format!("cannot work out the type of an argument to generic function '{}'", name);
+                Self::error_and_exit(&error_msg);
+            };
+            if !unify(param, &actual, &type_params, &mut bindings) {
+                let error_msg = format!(
+                    "mismatched argument to function '{}': expected {}, found {}",
+                    name, param, actual
+                );
+                Self::error_and_exit(&error_msg);
+            }
+        }
+
+        for (tp, bounds) in &type_params {
+            let Some(ty) = bindings.get(tp) else {
+                let error_msg = format!("cannot infer type parameter '{}' of function '{}'", tp, name);
+                Self::error_and_exit(&error_msg);
+            };
+            for bound in bounds {
+                if !self.implements(ty, bound) {
+                    let error_msg = format!(
+                        "type {} does not implement trait '{}', required by '{}' in function '{}'",
+                        ty, bound, tp, name
+                    );
+                    Self::error_and_exit(&error_msg);
+                }
+            }
+        }
+
+        let ret = subst_type(&returntype, &bindings);
+        if !self.type_params.is_empty() {
+            // still inside a template, nothing concrete to generate yet
+            return (name.to_string(), ret);
+        }
+
+        (self.instantiate(name, &type_params, &bindings), ret)
+    }
+
+    fn instantiate(&mut self, name: &str, type_params: &[(String, Vec<String>)], bindings: &HashMap<String, Type>) -> String {
+        let suffix: Vec<String> = type_params.iter().map(|(tp, _)| mangle(&bindings[tp])).collect();
+        let inst_name = format!("{}__{}", name, suffix.join("__"));
+
+        if self.instantiated.insert(inst_name.clone()) {
+            let mut inst = self.generics[name].clone();
+            subst_stmt(&mut inst, bindings);
+            if let Stmt::Func { name, type_params, .. } = &mut inst {
+                *name = inst_name.clone();
+                type_params.clear();
+            }
+
+            // we may be halfway through another function, check the copy from a clean slate
+            let globals = self.scopes[0].clone();
+            let saved = std::mem::replace(&mut self.scopes, vec![globals]);
+            let saved_closures = std::mem::take(&mut self.closure_stack);
+            self.check_func(&mut inst, None);
+            self.closure_stack = saved_closures;
+            self.scopes = saved;
+
+            self.instances.entry(name.to_string()).or_default().push(inst);
+        }
+
+        inst_name
+    }
+
+    fn implements(&self, ty: &Type, trait_name: &str) -> bool {
+        match ty {
+            Type::Custom(name) => match self.type_params.get(name) {
+                Some(bounds) => bounds.iter().any(|bound| bound == trait_name),
+                None => self.impls.contains(&(name.clone(), trait_name.to_string())),
+            },
+            _ => false,
+        }
+    }
+
+    fn find_method(&self, ty: &Type, method: &str) -> Option<MethodSig> {
+        let Type::Custom(type_name) = ty else {
+            return builtin_method(ty, method);
+        };
+
+        match self.type_params.get(type_name) {
+            // a type parameter only has the methods its bounds promise
+            Some(bounds) => {
+                let self_map = HashMap::from([("Self".to_string(), Type::Custom(type_name.to_string()))]);
+                bounds
+                    .iter()
+                    .filter_map(|bound| self.traits.get(bound))
+                    .flatten()
+                    .find(|req| req.name == method)
+                    .map(|req| MethodSig {
+                        receiver: req.receiver,
+                        params: req.params.iter().map(|(_, ty)| subst_type(ty, &self_map)).collect(),
+                        ret: subst_type(&req.returntype, &self_map),
+                    })
+            }
+            None => self
+                .methods
+                .get(type_name)
+                .and_then(|table| table.get(method))
+                .cloned()
+                .or_else(|| builtin_method(ty, method)),
+        }
+    }
+
+    // print's C _Generic only knows numbers, Bool, Char and String,
+    // anything else is turned into a String first
+    fn show_value(&mut self, value: &mut Expr, ty: &Type) {
+        if *ty == Type::Bool {
+            // `true` and `a < b` are plain ints in C, _Generic needs to see a bool
+            wrap_in_cast(value, ty);
+            return;
+        }
+        if ty.is_numeric() || matches!(ty, Type::Char | Type::String) {
+            return;
+        }
+        self.use_display(ty);
+        let inner = std::mem::replace(value, Expr::Nil);
+        *value = Expr::ToString { value: Box::new(inner), ty: ty.clone() };
+    }
+
+    // remember ty, and every type inside it, as needing a generated formatter
+    fn use_display(&mut self, ty: &Type) {
+        if !self.type_params.is_empty() || self.display_types.contains(ty) {
+            return;
+        }
+        match ty {
+            Type::Custom(name) => {
+                self.display_types.push(ty.clone());
+                let fields = self.structs.get(name).cloned().unwrap_or_default();
+                for (_, fty) in &fields {
+                    self.use_display(fty);
+                }
+            }
+            Type::Array(elem) => {
+                self.display_types.push(ty.clone());
+                self.use_display(elem);
+            }
+            Type::Option(inner) => {
+                self.display_types.push(ty.clone());
+                self.use_display(inner);
+            }
+            Type::Result(ok, err) => {
+                self.display_types.push(ty.clone());
+                self.use_display(ok);
+                self.use_display(err);
+            }
+            Type::Tuple(elems) => {
+                self.display_types.push(ty.clone());
+                for elem in elems {
+                    self.use_display(elem);
+                }
+            }
+            Type::Map(key, value) => {
+                self.display_types.push(ty.clone());
+                self.use_display(key);
+                self.use_display(value);
+            }
+            Type::Set(elem) => {
+                self.display_types.push(ty.clone());
+                self.use_display(elem);
+            }
+            _ => {}
+        }
+    }
+
+    // a value inside "...{value:spec}...", shown the same way print shows it
+    fn check_interpolated(&mut self, value: &mut Expr, spec: &mut FormatSpec) {
+        let ty = self.check_expr(value);
+        let numeric = ty.as_ref().is_some_and(|ty| ty.is_numeric());
+        if let Some(ty) = &ty {
+            if let Some(precision) = spec.precision
+                && !ty.is_float()
+            {
+                let error_msg = format!("precision '.{}' only works on Float values, not {}", precision, ty);
+                Self::error_and_exit(&error_msg);
+            }
+            if spec.zero && !numeric {
+                let error_msg = format!("'0' padding only works on numbers, not {}", ty);
+                Self::error_and_exit(&error_msg);
+            }
+            self.show_value(value, ty);
+        }
+        // numbers line up on the right by default, everything else on the left
+        if spec.width.is_some() && spec.align.is_none() {
+            spec.align = Some(if numeric { Align::Right } else { Align::Left });
+        }
+    }
+
+    // print("x = {}, y = {:.2}", x, y) is print("x = {x}, y = {y:.2}"), placeholders and
+    // arguments have to match up. Leaves a single argument behind.
+    fn fill_format_args(name: &str, args: &mut Vec<Expr>) {
+        if args.is_empty() {
+            let error_msg = format!("{}() needs something to print", name);
+            Self::error_and_exit(&error_msg);
+        }
+        let values = args.split_off(1);
+        if let Expr::String(s) = &mut args[0]
+            && !values.is_empty()
+        {
+            args[0] = Expr::Interpolation(vec![InterpPart::Lit(std::mem::take(s))]);
+        }
+
+        let parts = match &mut args[0] {
+            Expr::Interpolation(parts) => parts,
+            _ if values.is_empty() => return,
+            _ => {
+                let error_msg = format!("{}() with several arguments needs a string literal with '{{}}' placeholders first", name);
+                Self::error_and_exit(&error_msg);
+            }
+        };
+
+        let holes = parts.iter().filter(|part| matches!(part, InterpPart::Placeholder(_))).count();
+        if holes != values.len() {
+            let error_msg = format!(
+                "format string of {}() has {} placeholder(s) but {} argument(s) were given",
+                name,
+                holes,
+                values.len()
+            );
+            Self::error_and_exit(&error_msg);
+        }
+        let mut values = values.into_iter();
+        for part in parts.iter_mut() {
+            if let InterpPart::Placeholder(spec) = part {
+                *part = InterpPart::Expr(values.next().expect("counted above"), spec.clone());
+            }
+        }
+    }
+
+    fn check_func(&mut self, func: &mut Stmt, owner: Option<&str>) {
+        if let Stmt::Func { receiver, params, returntype, body, .. } = func {
+            self.push_scope();
+            if let (Some(owner), Some(receiver)) = (owner, receiver) {
+                self.declare("self", Type::Custom(owner.to_string()), *receiver == Receiver::Mut);
+            }
+            // a parameter is the callee's own copy, or a handle it shares with the caller for Map and Set
+            for (pname, pty) in params.iter() {
+                self.use_type(pty);
+                self.declare(pname, pty.clone(), true);
+            }
+            self.use_type(returntype);
+            let saved_ret = self.ret_type.replace(returntype.clone());
+
+            self.check_block(body);
+
+            self.ret_type = saved_ret;
+            self.pop_scope();
+        }
+    }
+
+    fn check_block(&mut self, block: &mut Block) {
+        self.push_scope();
+        for stmt in block {
+            self.check_stmt(stmt);
+        }
+        self.pop_scope();
+    }
+
+    fn check_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Let { name, mutable, valuetype, value } => {
+                let what = format!("'{}'", name);
+                let ty = self.check_let(&what, valuetype, value.as_mut());
+                self.declare(name, ty, *mutable);
+            }
+
+            Stmt::Const { name, valuetype, value } => {
+                let what = format!("const '{}'", name);
+                let ty = self.check_let(&what, valuetype, Some(value));
+                let Some(folded) = self.const_eval(value, Some(&ty)) else {
+                    let error_msg = format!("the value of const '{}' must be known at compile time", name);
+                    Self::error_and_exit(&error_msg);
+                };
+                *value = const_to_expr(folded.clone(), &ty);
+                self.declare_const(name, ty, folded);
+            }
+
+            Stmt::LetTuple { names, mutable, valuetype, value } => {
+                let what = format!("({})", names.join(", "));
+                let valuetype = self.check_let(&what, valuetype, Some(value));
+                let Type::Tuple(elems) = &valuetype else {
+                    let error_msg = format!("cannot destructure ({}) from non tuple type {}", names.join(", "), valuetype);
+                    Self::error_and_exit(&error_msg);
+                };
+                if elems.len() != names.len() {
+                    let error_msg = format!(
+                        "tuple pattern ({}) has {} name(s), but type {} has {} element(s)",
+                        names.join(", "),
+                        names.len(),
+                        valuetype,
+                        elems.len()
+                    );
+                    Self::error_and_exit(&error_msg);
+                }
+                for (name, ty) in names.iter().zip(elems) {
+                    self.declare(name, ty.clone(), *mutable);
+                }
+            }
+
+            Stmt::ExprStmt(expr) => {
+                self.check_expr(expr);
+            }
+
+            Stmt::Return(expr_opt) => {
+                if let Some(expr) = expr_opt {
+                    let ret_type = self.ret_type.clone();
+                    let actual = self.check_expr_with(expr, ret_type.as_ref());
+                    if let Some(ret_type) = &ret_type {
+                        Self::check_matches(ret_type, actual, "return value");
+                    }
+                }
+            }
+
+            Stmt::While { cond, body } => {
+                self.check_expr(cond);
+                self.check_block(body);
+            }
+
+            Stmt::For { var, iter, body, ty } => {
+                let Some(mut iter_ty) = self.check_expr(iter) else {
+                    Self::error_and_exit("cannot work out the type of the value being looped over");
+                };
+                // a map is looped over by its keys and a set by its elements,
+                // both copied out first so the body may change the collection
+                let to_array = match &iter_ty {
+                    Type::Map(key, _) => Some(("keys", key.clone())),
+                    Type::Set(elem) => Some(("to_array", elem.clone())),
+                    _ => None,
+                };
+                if let Some((method, elem)) = to_array {
+                    let target = std::mem::replace(iter, Expr::Nil);
+                    *iter = Expr::MethodCall {
+                        target: Box::new(target),
+                        method: method.to_string(),
+                        args: Vec::new(),
+                        owner: Some(iter_ty),
+                    };
+                    iter_ty = Type::Array(elem);
+                }
+                let elem = match &iter_ty {
+                    Type::String => Type::Char,
+                    Type::Array(elem) => (**elem).clone(),
+                    other => {
+                        let error_msg = format!("cannot loop over a value of type {}", other);
+                        Self::error_and_exit(&error_msg);
+                    }
+                };
+                *ty = Some(iter_ty);
+                self.push_scope();
+                self.declare(var, elem, false);
+                self.check_block(body);
+                self.pop_scope();
+            }
+
+            Stmt::If { cond, then_branch, else_branch } => {
+                self.check_expr(cond);
+                self.check_block(then_branch);
+                match else_branch {
+                    Some(BlockOrIf::Block(block)) => self.check_block(block),
+                    Some(BlockOrIf::If(nested)) => self.check_stmt(nested),
+                    None => {}
+                }
+            }
+
+            Stmt::Block(block) => self.check_block(block),
+
+            Stmt::Out | Stmt::Skip | Stmt::Import { .. } | Stmt::Struct { .. } => {}
+
+            Stmt::Func { .. } | Stmt::Impl { .. } | Stmt::Trait { .. } => {
+                // only meaningful at top-level, handled in check_program()
+            }
+        }
+    }
+
+    // checks a let initializer against its annotation, or fills the annotation
+    // in from the initializer when there is none. Returns the variable's type.
+    fn check_let(&mut self, what: &str, valuetype: &mut Option<Type>, value: Option<&mut Expr>) -> Type {
+        if let Some(ty) = valuetype {
+            self.use_type(ty);
+        }
+
+        let actual = value.map(|expr| self.check_expr_with(expr, valuetype.as_ref()));
+        match (&valuetype, actual) {
+            (Some(ty), Some(actual)) => {
+                Self::check_matches(ty, actual, &format!("initializer of {}", what));
+            }
+            (Some(_), None) => {}
+            (None, Some(Some(Type::Nil))) => {
+                let error_msg = format!("cannot declare {} from a nil value", what);
+                Self::error_and_exit(&error_msg);
+            }
+            (None, Some(Some(actual))) => *valuetype = Some(actual),
+            (None, Some(None)) => {
+                let error_msg = format!("cannot infer the type of {}, add a type annotation", what);
+                Self::error_and_exit(&error_msg);
+            }
+            (None, None) => {
+                let error_msg = format!("{} needs a type annotation or an initializer", what);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+
+        valuetype.clone().expect("filled in above")
+    }
+
+    // works out a constant expression, None when it depends on something only known at runtime.
+    // `ty` is the type the value ends up as, integers are kept inside it like C would.
+    fn const_eval(&self, expr: &Expr, ty: Option<&Type>) -> Option<ConstVal> {
+        let int_ty = ty.filter(|ty| ty.is_integer()).unwrap_or(&Type::Int);
+        let val = match expr {
+            Expr::Int(v) => ConstVal::Int(*v as i128),
+            Expr::Float(v) => ConstVal::Float(*v),
+            Expr::Bool(b) => ConstVal::Bool(*b),
+            Expr::String(s) => ConstVal::Str(s.clone()),
+            Expr::Char(c) => ConstVal::Char(*c),
+            Expr::Group(inner) => return self.const_eval(inner, ty),
+            Expr::Ident(name) => self.lookup(name)?.value.clone()?,
+
+            Expr::Prefix { op, rhs } => match (op, self.const_eval(rhs, ty)?) {
+                (PrefixOp::Neg, ConstVal::Int(v)) => ConstVal::Int(fit_const(-v, int_ty)),
+                (PrefixOp::Neg, ConstVal::Float(v)) => ConstVal::Float(-v),
+                (PrefixOp::Not, ConstVal::Bool(b)) => ConstVal::Bool(!b),
+                (PrefixOp::BitNot, ConstVal::Int(v)) => ConstVal::Int(wrap_int(!v, int_ty)),
+                _ => return None,
+            },
+
+            Expr::Infix { op, lhs, rhs } => {
+                let operand_ty = match op {
+                    InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div | InfixOp::Mod
+                    | InfixOp::BitAnd | InfixOp::BitOr | InfixOp::BitXor | InfixOp::Shl | InfixOp::Shr => ty,
+                    _ => None,
+                };
+                let lhs = self.const_eval(lhs, operand_ty)?;
+                let rhs_ty = if matches!(op, InfixOp::Shl | InfixOp::Shr) { None } else { operand_ty };
+                let rhs = self.const_eval(rhs, rhs_ty)?;
+                fold_infix(*op, lhs, rhs, int_ty, ty)?
+            }
+
+            Expr::Cast { value, ty: target } => {
+                // look through the float_to_* call the checker put around a float
+                let value = match &**value {
+                    Expr::Call { callee, args } if matches!(&**callee, Expr::Ident(name) if name.starts_with("float_to_")) => &args[0],
+                    value => value,
+                };
+                let value = self.const_eval(value, None)?;
+                fold_cast(value, target)?
+            }
+
+            Expr::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        InterpPart::Lit(s) => text.push_str(s),
+                        // padding and precision are left to the runtime
+                        InterpPart::Expr(_, spec) if spec.width.is_some() || spec.precision.is_some() => return None,
+                        InterpPart::Placeholder(_) => return None,
+                        InterpPart::Expr(value, _) => match self.const_eval(value, None)? {
+                            ConstVal::Int(v) => text.push_str(&v.to_string()),
+                            // an F32 prints with fewer digits, leave it to the runtime when it could be one
+                            ConstVal::Float(v) if v as f32 as f64 == v && format_float(v, 9) != format_float(v, 17) => return None,
+                            ConstVal::Float(v) => text.push_str(&format_float(v, 17)),
+                            ConstVal::Bool(b) => text.push_str(if b { "true" } else { "false" }),
+                            ConstVal::Str(s) => text.push_str(&s),
+                            ConstVal::Char(c) => text.push(c),
+                        },
+                    }
+                }
+                ConstVal::Str(text)
+            }
+
+            _ => return None,
+        };
+
+        // `const X: Float = 2;`
+        match (val, ty) {
+            (ConstVal::Int(v), Some(ty)) if ty.is_float() => Some(ConstVal::Float(v as f64)),
+            (ConstVal::Float(v), Some(Type::F32)) => Some(ConstVal::Float(v as f32 as f64)),
+            (val, _) => Some(val),
+        }
+    }
+
+    fn field_type(&self, ty: &Type, field: &str) -> Option<Type> {
+        match ty {
+            Type::Custom(name) => self
+                .structs
+                .get(name)
+                .and_then(|fields| fields.iter().find(|(fname, _)| fname == field))
+                .map(|(_, ty)| ty.clone()),
+            _ => None,
+        }
+    }
+
+    // `double` used as a value becomes `|__arg0: Int| => double(__arg0)`
+    fn func_value(&mut self, name: &str) -> Expr {
+        if let Some(closure) = self.func_values.get(name) {
+            return closure.clone();
+        }
+
+        let sig = self.funcs[name].clone();
+        let params: Vec<(String, Type)> = sig
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| (format!("__arg{}", i), ty.clone()))
+            .collect();
+        let call = Expr::Call {
+            callee: Box::new(Expr::Ident(name.to_string())),
+            args: params.iter().map(|(pname, _)| Expr::Ident(pname.clone())).collect(),
+        };
+        let mut closure = Expr::Closure { params, body: Box::new(call), info: None };
+        self.check_expr(&mut closure);
+
+        if self.type_params.is_empty() {
+            self.func_values.insert(name.to_string(), closure.clone());
+        }
+        closure
+    }
+
+    fn is_math_module(&self, target: &Expr) -> bool {
+        matches!(target, Expr::Ident(name) if name == "math") && self.lookup("math").is_none()
+    }
+
+    // math.PI is a Float literal, math.sqrt without a call is a function value
+    fn math_member(&mut self, expr: &mut Expr, field: &str, expected: Option<&Type>) -> Option<Type> {
+        let constant = match field {
+            "PI" => Some(std::f64::consts::PI),
+            "TAU" => Some(std::f64::consts::TAU),
+            "E" => Some(std::f64::consts::E),
+            _ => None,
+        };
+        if let Some(value) = constant {
+            *expr = Expr::Float(value);
+            return self.infer_expr(expr, expected);
+        }
+
+        let name = format!("math.{}", field);
+        if !is_math_func(&name) {
+            let error_msg = format!("the math module has no function or constant '{}'", field);
+            Self::error_and_exit(&error_msg);
+        }
+        // math.abs and co take any number type, the annotation says which one
+        let params = match (builtin_func(&name), expected) {
+            (Some(sig), _) => sig.params,
+            (None, Some(Type::Func(params, _))) => params.clone(),
+            (None, _) => {
+                let error_msg = format!(
+                    "math.{0} works on any number type, write the one you want, e.g. `let f: fn(Int) => Int = math.{0};`",
+                    field
+                );
+                Self::error_and_exit(&error_msg);
+            }
+        };
+
+        let key = format!("{}({:?})", name, params);
+        if let Some(closure) = self.func_values.get(&key) {
+            *expr = closure.clone();
+            return self.check_expr(expr);
+        }
+        let params: Vec<(String, Type)> = params
+            .into_iter()
+            .enumerate()
+            .map(|(i, ty)| (format!("__arg{}", i), ty))
+            .collect();
+        let call = Expr::Call {
+            callee: Box::new(Expr::Ident(name)),
+            args: params.iter().map(|(pname, _)| Expr::Ident(pname.clone())).collect(),
+        };
+        *expr = Expr::Closure { params, body: Box::new(call), info: None };
+        let ty = self.check_expr(expr);
+        if self.type_params.is_empty() {
+            self.func_values.insert(key, expr.clone());
+        }
+        ty
+    }
+
+    fn writes_through_immutable_self(&self, place: &Expr) -> bool {
+        place_root(place) == Some("self")
+            && self.lookup("self").is_some_and(|var| !var.mutable)
+    }
+
+    // a `let` binding or loop variable that wasn't declared `mut`
+    fn immutable_binding<'e>(&self, place: &'e Expr) -> Option<&'e str> {
+        let root = place_root(place)?;
+        let var = self.lookup(root)?;
+        (root != "self" && !var.mutable && var.value.is_none()).then_some(root)
+    }
+
+    fn check_args(&mut self, what: &str, params: &[Type], args: &mut [Expr]) {
+        if params.len() != args.len() {
+            let error_msg = format!(
+                "{} expects {} argument(s), but got {}",
+                what,
+                params.len(),
+                args.len()
+            );
+            Self::error_and_exit(&error_msg);
+        }
+
+        for (param, arg) in params.iter().zip(args.iter_mut()) {
+            let actual = self.check_expr_with(arg, Some(param));
+            Self::check_matches(param, actual, &format!("argument to {}", what));
+        }
+    }
+
+    // math.min(a, b) and friends take any number type, the same one for every
+    // argument. The first non-literal argument decides it, so math.max(0, x)
+    // with x: U8 is a U8
+    fn check_math_args(&mut self, name: &str, arity: usize, args: &mut [Expr], expected: Option<&Type>) -> Type {
+        if args.len() != arity {
+            let error_msg = format!("function '{}' expects {} argument(s), but got {}", name, arity, args.len());
+            Self::error_and_exit(&error_msg);
+        }
+        let anchor = args.iter().position(|arg| !is_untyped_literal(arg)).unwrap_or(0);
+        let hint = expected.filter(|ty| ty.is_numeric());
+        let ty = match self.check_expr_with(&mut args[anchor], hint) {
+            Some(ty) if ty.is_numeric() => ty,
+            Some(ty) => {
+                let error_msg = format!("{}() works on numbers, not {}", name, ty);
+                Self::error_and_exit(&error_msg);
+            }
+            None => {
+                let error_msg = format!("cannot work out the type of the arguments to {}()", name);
+                Self::error_and_exit(&error_msg);
+            }
+        };
+        for (i, arg) in args.iter_mut().enumerate() {
+            if i != anchor {
+                let actual = self.check_expr_with(arg, Some(&ty));
+                Self::check_matches(&ty, actual, &format!("argument to function '{}'", name));
+            }
+        }
+        ty
+    }
+
+    fn check_matches(expected: &Type, actual: Option<Type>, what: &str) {
+        if let Some(actual) = actual
+            && actual != *expected
+        {
+            let error_msg = format!("mismatched types in {}: expected {}, found {}", what, expected, actual);
+            Self::error_and_exit(&error_msg);
+        }
+    }
+
+    fn check_int_literal(value: i128, ty: &Type) {
+        let (min, max) = int_range(ty);
+        if value < min || value > max {
+            let error_msg = format!("integer literal {} does not fit in {} ({}..={})", value, ty, min, max);
+            Self::error_and_exit(&error_msg);
+        }
+    }
+
+    // numbers of different types never mix silently, one side needs an `as`.
+    // Int and Float mixing is the one exception, it gives a Float.
+    // Chars only compare with other Chars and can't do arithmetic.
+    fn check_operands(op: InfixOp, lhs: Option<&Type>, rhs: Option<&Type>) {
+        let (Some(lhs), Some(rhs)) = (lhs, rhs) else { return };
+        if *lhs == Type::String || *rhs == Type::String {
+            let is_add = matches!(op, InfixOp::Add | InfixOp::AddAssign);
+            if is_add && lhs != rhs {
+                let error_msg = format!("cannot add {} and {}, turn the other side into a String with to_string()", lhs, rhs);
+                Self::error_and_exit(&error_msg);
+            }
+            if is_add {
+                return;
+            }
+        }
+        if *lhs == Type::Char || *rhs == Type::Char {
+            let is_arith = matches!(
+                op,
+                InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div | InfixOp::Mod
+                    | InfixOp::AddAssign | InfixOp::SubAssign | InfixOp::MulAssign | InfixOp::DivAssign
+            );
+            if is_arith {
+                let error_msg = format!("cannot use '{}' on Char, convert it with 'as U32' first", op_symbol(op));
+                Self::error_and_exit(&error_msg);
+            }
+            if lhs != rhs {
+                let error_msg = format!("mismatched types for '{}': {} and {}", op_symbol(op), lhs, rhs);
+                Self::error_and_exit(&error_msg);
+            }
+            return;
+        }
+        if lhs == rhs || !lhs.is_numeric() || !rhs.is_numeric() {
+            return;
+        }
+        if matches!((lhs, rhs), (Type::Int, Type::Float) | (Type::Float, Type::Int)) {
+            return;
+        }
+        let error_msg = format!(
+            "mismatched numeric types for '{}': {} and {}, convert one side with 'as'",
+            op_symbol(op), lhs, rhs
+        );
+        Self::error_and_exit(&error_msg);
+    }
+
+    // bitwise operators only work on integers, and a literal shift amount
+    // must be smaller than the width of the value being shifted
+    fn check_bitwise_operands(op: InfixOp, lhs: Option<&Type>, rhs: Option<&Type>, rhs_expr: &Expr) {
+        for ty in [lhs, rhs].into_iter().flatten() {
+            if !ty.is_integer() {
+                let error_msg = format!("'{}' needs integer operands, found {}", op_symbol(op), ty);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+
+        if let (Some(ty), Expr::Int(amount)) = (lhs, rhs_expr)
+            && matches!(op, InfixOp::Shl | InfixOp::Shr | InfixOp::ShlAssign | InfixOp::ShrAssign)
+            && *amount >= int_bits(ty)
+        {
+            let error_msg = format!(
+                "shift amount {} is too large for {} ({} bits)",
+                amount,
+                ty,
+                int_bits(ty)
+            );
+            Self::error_and_exit(&error_msg);
+        }
+    }
+
+    // `some`, `none`, `ok` and `err` are constructors unless the program defines its own
+    fn builtin_variant(&self, name: &str) -> Option<Variant> {
+        if self.funcs.contains_key(name) || self.generics.contains_key(name) || self.lookup(name).is_some() {
+            return None;
+        }
+        match name {
+            "some" => Some(Variant::Some),
+            "none" => Some(Variant::None),
+            "ok" => Some(Variant::Ok),
+            "err" => Some(Variant::Err),
+            _ => None,
+        }
+    }
+
+    // works out the Option/Result type a constructor builds, `expected` fills in what the value can't tell us
+    fn check_variant(&mut self, variant: Variant, args: &mut [Expr], expected: Option<&Type>) -> Type {
+        let name = match variant {
+            Variant::Some => "some",
+            Variant::None => "none",
+            Variant::Ok => "ok",
+            Variant::Err => "err",
+        };
+        let wanted_args = if variant == Variant::None { 0 } else { 1 };
+        if args.len() != wanted_args {
+            let error_msg = format!("'{}' expects {} argument(s), but got {}", name, wanted_args, args.len());
+            Self::error_and_exit(&error_msg);
+        }
+
+        match (variant, expected) {
+            (Variant::None, Some(ty @ Type::Option(_))) => ty.clone(),
+            (Variant::None, _) => {
+                Self::error_and_exit("cannot infer the type of 'none', add a type annotation");
+            }
+
+            (Variant::Some, Some(ty @ Type::Option(inner))) => {
+                let actual = self.check_expr_with(&mut args[0], Some(inner));
+                Self::check_matches(inner, actual, "some(...)");
+                ty.clone()
+            }
+            (Variant::Some, _) => match self.check_expr(&mut args[0]) {
+                Some(inner) => Type::Option(Box::new(inner)),
+                None => Self::error_and_exit("cannot work out the type of the value inside 'some(...)'"),
+            },
+
+            (Variant::Ok | Variant::Err, Some(ty @ Type::Result(ok, err))) => {
+                let payload = if variant == Variant::Ok { ok } else { err };
+                let actual = self.check_expr_with(&mut args[0], Some(payload));
+                Self::check_matches(payload, actual, &format!("{}(...)", name));
+                ty.clone()
+            }
+            (Variant::Ok | Variant::Err, _) => {
+                let error_msg = format!("cannot infer the Result type of '{}(...)', add a type annotation", name);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+    }
+
+    // returns None when the type can't be worked out (e.g. untyped prelude calls)
+    fn check_expr(&mut self, expr: &mut Expr) -> Option<Type> {
+        self.check_expr_with(expr, None)
+    }
+
+    // `expected` is the type the surrounding code wants, which is how
+    // `none` or `ok(..)` learn their full type
+    fn check_expr_with(&mut self, expr: &mut Expr, expected: Option<&Type>) -> Option<Type> {
+        let ty = self.infer_expr(expr, expected);
+        if let Some(ty) = &ty {
+            self.use_type(ty);
+        }
+        ty
+    }
+
+    fn infer_expr(&mut self, expr: &mut Expr, expected: Option<&Type>) -> Option<Type> {
+        match expr {
+            // a plain literal takes the numeric type the context asks for, if it fits
+            Expr::Int(v) => {
+                let ty = expected.filter(|ty| ty.is_integer()).cloned().unwrap_or(Type::Int);
+                Self::check_int_literal(*v as i128, &ty);
+                Some(ty)
+            }
+            Expr::Float(v) => match expected {
+                Some(Type::F32) => {
+                    if v.abs() > f32::MAX as f64 {
+                        let error_msg = format!("float literal {:?} does not fit in F32", v);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    Some(Type::F32)
+                }
+                _ => Some(Type::Float),
+            },
+            Expr::Bool(_) => Some(Type::Bool),
+            Expr::String(_) => Some(Type::String),
+            Expr::Char(_) => Some(Type::Char),
+            Expr::Nil => Some(Type::Nil),
+
+            Expr::Interpolation(parts) => {
+                for part in parts.iter_mut() {
+                    match part {
+                        InterpPart::Expr(value, spec) => self.check_interpolated(value, spec),
+                        InterpPart::Placeholder(_) => Self::error_and_exit(
+                            "'{}' only gets a value in print/println/eprint/eprintln/format calls, put an expression inside or write '{{}}'",
+                        ),
+                        InterpPart::Lit(_) => {}
+                    }
+                }
+                Some(Type::String)
+            }
+
+            Expr::Ident(name) => {
+                if name == "self" && self.lookup("self").is_none() {
+                    Self::error_and_exit("'self' can only be used inside methods");
+                }
+                if let Some(Variant::None) = self.builtin_variant(name) {
+                    let ty = self.check_variant(Variant::None, &mut [], expected);
+                    *expr = Expr::Variant { variant: Variant::None, value: None, ty: ty.clone() };
+                    return Some(ty);
+                }
+                if let Some((depth, var)) = self.lookup_depth(name) {
+                    let ty = var.ty.clone();
+                    let name = name.clone();
+                    self.note_capture(&name, &ty, depth);
+                    return Some(ty);
+                }
+                if self.funcs.contains_key(name.as_str()) {
+                    // a named function used as a value
+                    let name = name.clone();
+                    let closure = self.func_value(&name);
+                    *expr = closure;
+                    return self.check_expr(expr);
+                }
+                if self.generics.contains_key(name.as_str()) {
+                    let error_msg = format!("generic function '{}' can't be used as a value, wrap it in a closure", name);
+                    Self::error_and_exit(&error_msg);
+                }
+                None
+            }
+
+            Expr::Closure { params, body, info } => {
+                let param_types: Vec<Type> = params.iter().map(|(_, ty)| ty.clone()).collect();
+                if let Some(info) = info {
+                    return Some(Type::Func(param_types, Box::new(info.ret.clone())));
+                }
+
+                self.closure_stack.push(ClosureFrame { base: self.scopes.len(), captures: Vec::new() });
+                self.push_scope();
+                for (pname, pty) in params.iter() {
+                    self.use_type(pty);
+                    self.declare(pname, pty.clone(), true);
+                }
+                // `return` and `?` inside a closure body don't belong to the enclosing function
+                let saved_ret = self.ret_type.take();
+                let ret = self.check_expr(body);
+                self.ret_type = saved_ret;
+                self.pop_scope();
+                let frame = self.closure_stack.pop().expect("pushed above");
+
+                // untyped prelude calls in the body fall back to the type the context asks for
+                let ret = match (ret, expected) {
+                    (Some(ret), _) => ret,
+                    (None, Some(Type::Func(_, expected_ret))) => (**expected_ret).clone(),
+                    (None, _) => Self::error_and_exit("cannot work out the return type of this closure"),
+                };
+                let name = format!("__closure_{}", self.closures.len());
+                *info = Some(ClosureInfo { name, captures: frame.captures, ret: ret.clone() });
+                if self.type_params.is_empty() {
+                    self.closures.push(expr.clone());
+                }
+                Some(Type::Func(param_types, Box::new(ret)))
+            }
+
+            Expr::ClosureCall { args, fn_type, .. } => {
+                let Type::Func(params, ret) = fn_type.clone() else {
+                    unreachable!("closure call through non function type");
+                };
+                self.check_args("function value", &params, args);
+                Some(*ret)
+            }
+
+            Expr::Group(inner) => self.check_expr_with(inner, expected),
+
+            Expr::Variant { ty, .. } => Some(ty.clone()),
+
+            Expr::Try { value, value_type, ret_type } => {
+                let ty = self.check_expr(value);
+                let result = match (&ty, &self.ret_type) {
+                    (Some(Type::Option(inner)), Some(Type::Option(_))) => (**inner).clone(),
+                    (Some(Type::Result(ok, err)), Some(Type::Result(_, fn_err))) => {
+                        if err != fn_err {
+                            let error_msg = format!(
+                                "'?' can't pass an error of type {} out of a function whose error type is {}",
+                                err, fn_err
+                            );
+                            Self::error_and_exit(&error_msg);
+                        }
+                        (**ok).clone()
+                    }
+                    (Some(Type::Option(_)), _) => {
+                        Self::error_and_exit("'?' on an Option can only be used in a function that returns an Option");
+                    }
+                    (Some(Type::Result(..)), _) => {
+                        Self::error_and_exit("'?' on a Result can only be used in a function that returns a Result");
+                    }
+                    (Some(other), _) => {
+                        let error_msg = format!("'?' needs an Option or Result, found {}", other);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    (None, _) => Self::error_and_exit("cannot work out the type of the value '?' is applied to"),
+                };
+                *value_type = ty;
+                *ret_type = self.ret_type.clone();
+                Some(result)
+            }
+
+            Expr::Prefix { op, rhs } => match op {
+                PrefixOp::Neg => {
+                    // -128 fits in I8 even though 128 doesn't
+                    if let Expr::Int(v) = **rhs {
+                        let ty = expected.filter(|ty| ty.is_integer()).cloned().unwrap_or(Type::Int);
+                        Self::check_int_literal(-(v as i128), &ty);
+                        return Some(ty);
+                    }
+                    let rhs_ty = self.check_expr_with(rhs, expected.filter(|ty| ty.is_numeric()));
+                    if let Some(ty) = &rhs_ty
+                        && ty.is_unsigned()
+                    {
+                        let error_msg = format!("cannot negate a value of unsigned type {}", ty);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    if let Some(ty) = &rhs_ty
+                        && is_promoted(ty)
+                    {
+                        wrap_in_cast(expr, ty);
+                    }
+                    rhs_ty
+                }
+                PrefixOp::Not => {
+                    self.check_expr(rhs);
+                    Some(Type::Bool)
+                }
+                PrefixOp::BitNot => {
+                    let rhs_ty = self.check_expr_with(rhs, expected.filter(|ty| ty.is_integer()));
+                    if let Some(ty) = &rhs_ty {
+                        if !ty.is_integer() {
+                            let error_msg = format!("'~' needs an integer, found {}", ty);
+                            Self::error_and_exit(&error_msg);
+                        }
+                        if is_promoted(ty) {
+                            wrap_in_cast(expr, ty);
+                        }
+                    }
+                    rhs_ty
+                }
+            },
+
+            Expr::Infix { op, lhs, rhs } => {
+                let is_arith = matches!(
+                    op,
+                    InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div | InfixOp::Mod
+                        | InfixOp::BitAnd | InfixOp::BitOr | InfixOp::BitXor | InfixOp::Shl | InfixOp::Shr
+                );
+                let is_shift = matches!(op, InfixOp::Shl | InfixOp::Shr | InfixOp::ShlAssign | InfixOp::ShrAssign);
+                let arith_expected = if is_arith { expected.filter(|ty| ty.is_numeric()) } else { None };
+                // an untyped literal takes its type from the other side: `1 + x` with x: U8
+                let (lhs_ty, rhs_ty) = if is_shift {
+                    // the shift amount's type doesn't have to match the value being shifted
+                    let lhs_ty = self.check_expr_with(lhs, arith_expected);
+                    let rhs_ty = self.check_expr(rhs);
+                    // C would shift a bare `1` as a 32 bit int
+                    if let Some(ty) = &lhs_ty
+                        && is_untyped_literal(lhs)
+                    {
+                        wrap_in_cast(lhs, ty);
+                    }
+                    (lhs_ty, rhs_ty)
+                } else if is_untyped_literal(lhs) && !is_untyped_literal(rhs) {
+                    let rhs_ty = self.check_expr_with(rhs, arith_expected);
+                    let lhs_ty = self.check_expr_with(lhs, rhs_ty.as_ref().or(arith_expected));
+                    (lhs_ty, rhs_ty)
+                } else {
+                    let lhs_ty = self.check_expr_with(lhs, arith_expected);
+                    let rhs_ty = self.check_expr_with(rhs, lhs_ty.as_ref().or(arith_expected));
+                    (lhs_ty, rhs_ty)
+                };
+                if is_bitwise(*op) {
+                    Self::check_bitwise_operands(*op, lhs_ty.as_ref(), rhs_ty.as_ref(), rhs);
+                }
+                if !is_shift {
+                    Self::check_operands(*op, lhs_ty.as_ref(), rhs_ty.as_ref());
+                }
+
+                match op {
+                    InfixOp::Assign
+                    | InfixOp::AddAssign
+                    | InfixOp::SubAssign
+                    | InfixOp::MulAssign
+                    | InfixOp::DivAssign
+                    | InfixOp::BitAndAssign
+                    | InfixOp::BitOrAssign
+                    | InfixOp::BitXorAssign
+                    | InfixOp::ShlAssign
+                    | InfixOp::ShrAssign => {
+                        if self.writes_through_immutable_self(lhs) {
+                            Self::error_and_exit(
+                                "cannot assign through 'self' in a method that takes 'self', declare it as 'mut self'",
+                            );
+                        }
+                        if let Some(root) = place_root(lhs)
+                            && self.lookup(root).is_some_and(|var| var.value.is_some())
+                        {
+                            let error_msg = format!("cannot assign to const '{}'", root);
+                            Self::error_and_exit(&error_msg);
+                        }
+                        if let Some(root) = self.immutable_binding(lhs) {
+                            let error_msg = format!("cannot assign to '{}', declare it with 'let mut'", root);
+                            Self::error_and_exit(&error_msg);
+                        }
+                        if let Some(root) = place_root(lhs)
+                            && self.is_captured(root)
+                        {
+                            let error_msg = format!(
+                                "closures capture variables by value, assigning to captured '{}' would be lost",
+                                root
+                            );
+                            Self::error_and_exit(&error_msg);
+                        }
+                        if matches!(op, InfixOp::AddAssign) && lhs_ty == Some(Type::String) {
+                            // C has no += for String, append in place
+                            let target = std::mem::replace(&mut **lhs, Expr::Nil);
+                            let value = std::mem::replace(&mut **rhs, Expr::Nil);
+                            *expr = Expr::MethodCall {
+                                target: Box::new(target),
+                                method: "push_str".to_string(),
+                                args: vec![value],
+                                owner: Some(Type::String),
+                            };
+                            self.note_builtin("String__push_str".to_string());
+                            return Some(Type::Nil);
+                        }
+                        lhs_ty
+                    }
+                    InfixOp::Add if lhs_ty == Some(Type::String) => {
+                        // "a" + b is "{a}{b}"
+                        let parts = [lhs, rhs].map(|side| InterpPart::Expr(std::mem::replace(&mut **side, Expr::Nil), FormatSpec::default()));
+                        *expr = Expr::Interpolation(parts.into());
+                        Some(Type::String)
+                    }
+                    InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div | InfixOp::Mod => {
+                        let ty = if lhs_ty == Some(Type::Float) || rhs_ty == Some(Type::Float) {
+                            Some(Type::Float)
+                        } else {
+                            lhs_ty.or(rhs_ty)
+                        };
+                        // C does small integer math in `int`, bring it back to the Butter type
+                        if let Some(ty) = &ty
+                            && is_promoted(ty)
+                        {
+                            wrap_in_cast(expr, ty);
+                        }
+                        ty
+                    }
+                    InfixOp::BitAnd | InfixOp::BitOr | InfixOp::BitXor | InfixOp::Shl | InfixOp::Shr => {
+                        if let Some(ty) = &lhs_ty
+                            && is_promoted(ty)
+                        {
+                            wrap_in_cast(expr, ty);
+                        }
+                        lhs_ty
+                    }
+                    InfixOp::Eq
+                    | InfixOp::Ne
+                    | InfixOp::Lt
+                    | InfixOp::Le
+                    | InfixOp::Gt
+                    | InfixOp::Ge
+                    | InfixOp::And
+                    | InfixOp::Or => Some(Type::Bool),
+                }
+            }
+
+            Expr::Call { callee, args } => {
+                if let Expr::Ident(name) = &**callee
+                    && let Some(variant) = self.builtin_variant(name)
+                    && variant != Variant::None
+                {
+                    let ty = self.check_variant(variant, args, expected);
+                    let value = args.pop().map(Box::new);
+                    *expr = Expr::Variant { variant, value, ty: ty.clone() };
+                    return Some(ty);
+                }
+
+                if let Expr::Ident(name) = &**callee
+                    && self.generics.contains_key(name)
+                {
+                    let name = name.clone();
+                    let (c_name, ret) = self.check_generic_call(&name, args);
+                    **callee = Expr::Ident(c_name);
+                    return Some(ret);
+                }
+
+                // calling a variable, field or any other expression of function type
+                let is_value_call = match &**callee {
+                    Expr::Ident(name) => self.lookup(name).is_some(),
+                    _ => true,
+                };
+                if is_value_call {
+                    return match self.check_expr(callee) {
+                        Some(fn_type @ Type::Func(..)) => {
+                            let callee = std::mem::replace(callee, Box::new(Expr::Nil));
+                            let args = std::mem::take(args);
+                            *expr = Expr::ClosureCall { callee, args, fn_type };
+                            self.check_expr(expr)
+                        }
+                        Some(other) => {
+                            let error_msg = format!("cannot call a value of type {}", other);
+                            Self::error_and_exit(&error_msg);
+                        }
+                        None => {
+                            for arg in args.iter_mut() {
+                                self.check_expr(arg);
+                            }
+                            None
+                        }
+                    };
+                }
+
+                let sig = match &**callee {
+                    Expr::Ident(name) => self.funcs.get(name).cloned().map(|sig| (name.clone(), sig)),
+                    _ => None,
+                };
+
+                match sig {
+                    Some((name, sig)) => {
+                        self.check_args(&format!("function '{}'", name), &sig.params, args);
+                        Some(sig.ret)
+                    }
+                    None => {
+                        let builtin = match &**callee {
+                            Expr::Ident(name) => name.clone(),
+                            _ => String::new(),
+                        };
+                        match builtin.as_str() {
+                            "format" => {
+                                Self::fill_format_args(&builtin, args);
+                                if !matches!(args[0], Expr::String(_) | Expr::Interpolation(_)) {
+                                    Self::error_and_exit("format() takes a string literal, e.g. format(\"{} items\", n)");
+                                }
+                                *expr = args.pop().expect("fill_format_args leaves one argument");
+                                self.check_expr(expr)
+                            }
+                            "array_new" => {
+                                if !args.is_empty() {
+                                    Self::error_and_exit("array_new() takes no arguments");
+                                }
+                                *expr = Expr::ArrayLiteral { elements: Vec::new(), ty: None };
+                                self.infer_expr(expr, expected)
+                            }
+                            // the old runtime's spellings of a[i], a[i] = v and [x, y]
+                            "array_get" | "array_set" => {
+                                let arity = if builtin == "array_get" { 2 } else { 3 };
+                                if args.len() != arity {
+                                    let error_msg = if arity == 2 {
+                                        "array_get() takes an array and an index"
+                                    } else {
+                                        "array_set() takes an array, an index and a value"
+                                    };
+                                    Self::error_and_exit(error_msg);
+                                }
+                                let value = (arity == 3).then(|| args.pop().expect("checked above"));
+                                let index = args.pop().expect("checked above");
+                                let target = args.pop().expect("checked above");
+                                let place = Expr::Index { target: Box::new(target), index: Box::new(index), ty: None };
+                                *expr = match value {
+                                    Some(value) => Expr::Infix { op: InfixOp::Assign, lhs: Box::new(place), rhs: Box::new(value) },
+                                    None => place,
+                                };
+                                self.infer_expr(expr, expected)
+                            }
+                            "array_from_literal" => {
+                                *expr = Expr::ArrayLiteral { elements: std::mem::take(args), ty: None };
+                                self.infer_expr(expr, expected)
+                            }
+                            "map_new" | "set_new" => {
+                                let ty = match (builtin.as_str(), expected) {
+                                    ("map_new", Some(ty @ Type::Map(..))) | ("set_new", Some(ty @ Type::Set(_))) => ty.clone(),
+                                    ("map_new", _) => Self::error_and_exit(
+                                        "map_new() needs a type to create, e.g. `let m: Map<String, Int> = map_new();`",
+                                    ),
+                                    _ => Self::error_and_exit("set_new() needs a type to create, e.g. `let s: Set<Int> = set_new();`"),
+                                };
+                                if !args.is_empty() {
+                                    let error_msg = format!("{}() takes no arguments", builtin);
+                                    Self::error_and_exit(&error_msg);
+                                }
+                                self.use_type(&ty);
+                                *expr = Expr::NewCollection { ty: ty.clone() };
+                                Some(ty)
+                            }
+                            "array_push" => {
+                                // the older spelling of arr.push(x)
+                                if args.len() != 2 {
+                                    Self::error_and_exit("array_push() takes an array and a value");
+                                }
+                                let value = args.pop().expect("checked above");
+                                let target = args.pop().expect("checked above");
+                                *expr = Expr::MethodCall {
+                                    target: Box::new(target),
+                                    method: "push".to_string(),
+                                    args: vec![value],
+                                    owner: None,
+                                };
+                                self.infer_expr(expr, expected)
+                            }
+                            "print" | "println" | "eprint" | "eprintln" => {
+                                Self::fill_format_args(&builtin, args);
+                                let arg = &mut args[0];
+                                if let Some(ty) = self.check_expr(arg) {
+                                    if builtin.starts_with('e') && ty != Type::String {
+                                        // stderr printing only takes strings in C
+                                        self.use_display(&ty);
+                                        let inner = std::mem::replace(arg, Expr::Nil);
+                                        *arg = Expr::ToString { value: Box::new(inner), ty };
+                                    } else {
+                                        self.show_value(arg, &ty);
+                                    }
+                                }
+                                Some(Type::Nil)
+                            }
+                            "math.abs" | "math.min" | "math.max" | "math.clamp" => {
+                                let arity = match builtin.as_str() {
+                                    "math.abs" => 1,
+                                    "math.clamp" => 3,
+                                    _ => 2,
+                                };
+                                let ty = self.check_math_args(&builtin, arity, args, expected);
+                                // one C version per kind of number, narrower types are cast back
+                                let kind = if ty.is_float() {
+                                    "f"
+                                } else if ty.is_unsigned() {
+                                    "u"
+                                } else {
+                                    "i"
+                                };
+                                let c_name = format!("{}_{}", builtin.replace('.', "_"), kind);
+                                **callee = Expr::Ident(c_name.clone());
+                                self.note_builtin(c_name);
+                                if !matches!(ty, Type::Int | Type::U64 | Type::Float) {
+                                    wrap_in_cast(expr, &ty);
+                                }
+                                Some(ty)
+                            }
+                            _ if is_int_arith(&builtin) => {
+                                let checked = builtin.starts_with("math.checked_");
+                                let hint = match expected {
+                                    Some(Type::Option(inner)) if checked => Some(&**inner),
+                                    _ if checked => None,
+                                    other => other,
+                                };
+                                let ty = self.check_math_args(&builtin, 2, args, hint);
+                                if !ty.is_integer() {
+                                    let error_msg = format!("{}() works on integers, not {}", builtin, ty);
+                                    Self::error_and_exit(&error_msg);
+                                }
+                                let c_name = format!("{}_{}", builtin.replace('.', "_"), ty);
+                                **callee = Expr::Ident(c_name.clone());
+                                self.note_builtin(c_name);
+                                let ret = if checked { Type::Option(Box::new(ty)) } else { ty };
+                                self.use_type(&ret);
+                                Some(ret)
+                            }
+                            _ if let Some(sig) = builtin_func(&builtin) => {
+                                self.check_args(&format!("function '{}'", builtin), &sig.params, args);
+                                self.use_type(&sig.ret);
+                                if builtin == "exit" {
+                                    // C's own exit() is already taken
+                                    **callee = Expr::Ident("process_exit".to_string());
+                                }
+                                // math.sqrt is math_sqrt in C
+                                let c_name = builtin.replace('.', "_");
+                                if c_name != builtin {
+                                    **callee = Expr::Ident(c_name.clone());
+                                }
+                                self.note_builtin(c_name);
+                                Some(sig.ret)
+                            }
+                            _ => {
+                                // prelude functions (array_push, ...) aren't typed yet
+                                for arg in args.iter_mut() {
+                                    self.check_expr(arg);
+                                }
+                                None
+                            }
+                        }
+                    }
+                }
+            }
+
+            Expr::MethodCall { target, method, args, owner } => {
+                // math.sqrt(x) calls the builtin, unless `math` is a variable
+                if self.is_math_module(target) {
+                    let name = format!("math.{}", method);
+                    if !is_math_func(&name) {
+                        let error_msg = format!("the math module has no function '{}'", method);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    *expr = Expr::Call { callee: Box::new(Expr::Ident(name)), args: std::mem::take(args) };
+                    return self.infer_expr(expr, expected);
+                }
+
+                let target_ty = match self.check_expr(target) {
+                    Some(ty) => ty,
+                    None => {
+                        let error_msg = format!("cannot work out the type of the value '{}' is called on", method);
+                        Self::error_and_exit(&error_msg);
+                    }
+                };
+
+                // 5.to_string() is "{5}", s.parse_int() is parse_int(s)
+                let is_primitive = target_ty.is_numeric() || matches!(target_ty, Type::Bool | Type::Char | Type::String);
+                if is_primitive && method == "to_string" && args.is_empty() {
+                    let value = std::mem::replace(&mut **target, Expr::Nil);
+                    *expr = Expr::Interpolation(vec![InterpPart::Expr(value, FormatSpec::default())]);
+                    return self.check_expr(expr);
+                }
+                if target_ty == Type::String && matches!(method.as_str(), "parse_int" | "parse_float" | "parse_bool") {
+                    let mut call_args = vec![std::mem::replace(&mut **target, Expr::Nil)];
+                    call_args.append(args);
+                    *expr = Expr::Call { callee: Box::new(Expr::Ident(method.clone())), args: call_args };
+                    return self.check_expr(expr);
+                }
+
+                // calling a struct field that holds a function: p.on_click(x)
+                if self.find_method(&target_ty, method).is_none()
+                    && let Some(fn_type @ Type::Func(..)) = self.field_type(&target_ty, method)
+                {
+                    let callee = Expr::FieldAccess {
+                        target: std::mem::replace(target, Box::new(Expr::Nil)),
+                        field: method.clone(),
+                    };
+                    let args = std::mem::take(args);
+                    *expr = Expr::ClosureCall { callee: Box::new(callee), args, fn_type };
+                    return self.check_expr(expr);
+                }
+
+                let sig = match (self.find_method(&target_ty, method), &target_ty) {
+                    (Some(sig), _) => sig,
+                    (None, Type::Custom(name)) if self.type_params.contains_key(name) => {
+                        let error_msg = format!("no method '{}' in the trait bounds of type parameter '{}'", method, name);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    (None, Type::Custom(name)) => {
+                        let error_msg = format!("no method '{}' found for struct '{}'", method, name);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    (None, other) => {
+                        let error_msg = format!("no method '{}' found for type {}", method, other);
+                        Self::error_and_exit(&error_msg);
+                    }
+                };
+
+                if sig.receiver == Receiver::Mut && self.writes_through_immutable_self(target) {
+                    let error_msg = format!(
+                        "cannot call 'mut self' method '{}' through 'self', declare the enclosing method as 'mut self'",
+                        method
+                    );
+                    Self::error_and_exit(&error_msg);
+                }
+                if sig.receiver == Receiver::Mut
+                    && let Some(root) = self.immutable_binding(target)
+                {
+                    let error_msg = format!(
+                        "cannot call 'mut self' method '{}' on '{}', declare it with 'let mut'",
+                        method, root
+                    );
+                    Self::error_and_exit(&error_msg);
+                }
+
+                self.check_args(&format!("method '{}.{}'", target_ty, method), &sig.params, args);
+                // LineReader.next(), s.split(",") and friends are written in C
+                let c_name = match &target_ty {
+                    Type::Custom(name)
+                        if self.methods.get(name).is_none_or(|table| !table.contains_key(method.as_str()))
+                            && !self.type_params.contains_key(name) =>
+                    {
+                        Some(format!("{}__{}", name, method))
+                    }
+                    Type::String => Some(format!("String__{}", method)),
+                    Type::Array(elem) if **elem == Type::String && method == "join" => Some("Array_String__join".to_string()),
+                    _ => None,
+                };
+                if let Some(c_name) = c_name {
+                    self.use_type(&sig.ret);
+                    self.note_builtin(c_name);
+                }
+                *owner = Some(target_ty);
+                Some(sig.ret)
+            }
+
+            Expr::Index { target, index, ty } => {
+                let target_ty = self.check_expr(target);
+                let index_ty = self.check_expr_with(index, Some(&Type::Int));
+                if let Some(index_ty) = index_ty
+                    && !index_ty.is_integer()
+                {
+                    let error_msg = format!("array index must be an integer, found {}", index_ty);
+                    Self::error_and_exit(&error_msg);
+                }
+                match target_ty {
+                    Some(Type::Array(elem)) => {
+                        *ty = Some(Type::Array(elem.clone()));
+                        Some(*elem)
+                    }
+                    Some(other) => {
+                        let error_msg = format!("cannot index into a value of type {}", other);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    None => Self::error_and_exit("cannot work out the type of the value being indexed"),
+                }
+            }
+
+            Expr::StructLiteral { name, fields } => {
+                let Some(decl) = self.structs.get(name.as_str()).cloned() else {
+                    let error_msg = format!("unknown struct '{}'", name);
+                    Self::error_and_exit(&error_msg);
+                };
+                for (fname, value) in fields.iter_mut() {
+                    let Some((_, fty)) = decl.iter().find(|(decl_name, _)| decl_name == fname) else {
+                        let error_msg = format!("struct '{}' has no field '{}'", name, fname);
+                        Self::error_and_exit(&error_msg);
+                    };
+                    let actual = self.check_expr_with(value, Some(fty));
+                    Self::check_matches(fty, actual, &format!("field '{}.{}'", name, fname));
+                }
+                Some(Type::Custom(name.clone()))
+            }
+
+            Expr::FieldAccess { target, field } => {
+                if self.is_math_module(target) {
+                    let field = field.clone();
+                    return self.math_member(expr, &field, expected);
+                }
+                let target_ty = self.check_expr(target)?;
+                self.field_type(&target_ty, field)
+            }
+
+            Expr::TupleLiteral { elements, ty } => {
+                let expected_elems = match expected {
+                    Some(Type::Tuple(elems)) if elems.len() == elements.len() => Some(elems),
+                    _ => None,
+                };
+                let mut elem_types = Vec::new();
+                for (i, elem) in elements.iter_mut().enumerate() {
+                    let expected_elem = expected_elems.map(|elems| &elems[i]);
+                    match self.check_expr_with(elem, expected_elem) {
+                        Some(elem_ty) => elem_types.push(elem_ty),
+                        None => {
+                            let error_msg = format!("cannot work out the type of tuple element {}", i);
+                            Self::error_and_exit(&error_msg);
+                        }
+                    }
+                }
+                let tuple = Type::Tuple(elem_types);
+                *ty = Some(tuple.clone());
+                Some(tuple)
+            }
+
+            Expr::TupleIndex { target, index } => {
+                match self.check_expr(target) {
+                    Some(Type::Tuple(elems)) => match elems.get(*index) {
+                        Some(elem) => Some(elem.clone()),
+                        None => {
+                            let error_msg = format!(
+                                "tuple index {} out of range for type {}",
+                                index,
+                                Type::Tuple(elems.clone())
+                            );
+                            Self::error_and_exit(&error_msg);
+                        }
+                    },
+                    Some(other) => {
+                        let error_msg = format!("cannot use .{} on non tuple type {}", index, other);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    None => Self::error_and_exit("cannot work out the type of the value being indexed with '.N'"),
+                }
+            }
+
+            // only built by the checker, after its value was checked
+            Expr::ToString { .. } => Some(Type::String),
+            Expr::NewCollection { ty } => Some(ty.clone()),
+
+            Expr::Cast { value, ty } => {
+                // `65 as Char` reads the literal as a U8
+                let mut value_expected = if *ty == Type::Char { Type::U8 } else { ty.clone() };
+                // `300 as U8` asks for the wrap, so a literal that doesn't fit is read as an Int first
+                if let Some(v) = int_literal_value(value)
+                    && value_expected.is_integer()
+                {
+                    let (min, max) = int_range(&value_expected);
+                    if v < min || v > max {
+                        value_expected = Type::Int;
+                    }
+                }
+                let from = self.check_expr_with(value, Some(&value_expected));
+                self.use_type(ty);
+                // a plain C cast is undefined for NaN and out of range floats
+                if let Some(from) = &from
+                    && from.is_float()
+                    && ty.is_integer()
+                {
+                    let inner = std::mem::replace(&mut **value, Expr::Nil);
+                    let callee = Expr::Ident(format!("float_to_{}", ty.to_string().to_lowercase()));
+                    **value = Expr::Call { callee: Box::new(callee), args: vec![inner] };
+                }
+                if let Some(from) = &from {
+                    let allowed = match (from, &*ty) {
+                        (Type::Char, to) => to.is_integer(),
+                        (Type::U8, Type::Char) => true,
+                        (from, to) => (from.is_numeric() || *from == Type::Bool) && to.is_numeric(),
+                    };
+                    if !allowed && *ty == Type::Char {
+                        let error_msg = format!("cannot cast {} to Char, only U8 can be cast to Char", from);
+                        Self::error_and_exit(&error_msg);
+                    }
+                    if !allowed {
+                        let error_msg = format!("cannot cast {} to {}", from, ty);
+                        Self::error_and_exit(&error_msg);
+                    }
+                }
+                Some(ty.clone())
+            }
+
+            Expr::ArrayLiteral { elements, ty } => {
+                let mut elem_ty = match expected {
+                    Some(Type::Array(elem)) => Some((**elem).clone()),
+                    _ => None,
+                };
+                for (i, elem) in elements.iter_mut().enumerate() {
+                    let actual = self.check_expr_with(elem, elem_ty.as_ref());
+                    match (&elem_ty, actual) {
+                        (None, actual) => elem_ty = actual,
+                        (Some(expected), actual) => Self::check_matches(expected, actual, &format!("array element {}", i)),
+                    }
+                }
+                // an empty `[]` with nothing to go on is an Array<Int>, like the old Array_int64_t
+                let elem_ty = elem_ty.unwrap_or(Type::Int);
+                let array = Type::Array(Box::new(elem_ty));
+                *ty = Some(array.clone());
+                Some(array)
+            }
+        }
+    }
+}
+
+// the variable an lvalue like `a.b[0].c` ultimately writes into
+fn place_root(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Ident(name) => Some(name),
+        Expr::FieldAccess { target, .. } | Expr::Index { target, .. } | Expr::TupleIndex { target, .. } => {
+            place_root(target)
+        }
+        Expr::Group(inner) => place_root(inner),
+        _ => None,
+    }
+}
+
+fn op_symbol(op: InfixOp) -> &'static str {
+    match op {
+        InfixOp::Add => "+",
+        InfixOp::Sub => "-",
+        InfixOp::Mul => "*",
+        InfixOp::Div => "/",
+        InfixOp::Mod => "%",
+        InfixOp::Eq => "==",
+        InfixOp::Ne => "!=",
+        InfixOp::Lt => "<",
+        InfixOp::Le => "<=",
+        InfixOp::Gt => ">",
+        InfixOp::Ge => ">=",
+        InfixOp::And => "&&",
+        InfixOp::Or => "||",
+        InfixOp::Assign => "=",
+        InfixOp::AddAssign => "+=",
+        InfixOp::SubAssign => "-=",
+        InfixOp::MulAssign => "*=",
+        InfixOp::DivAssign => "/=",
+        InfixOp::BitAnd => "&",
+        InfixOp::BitOr => "|",
+        InfixOp::BitXor => "^",
+        InfixOp::Shl => "<<",
+        InfixOp::Shr => ">>",
+        InfixOp::BitAndAssign => "&=",
+        InfixOp::BitOrAssign => "|=",
+        InfixOp::BitXorAssign => "^=",
+        InfixOp::ShlAssign => "<<=",
+        InfixOp::ShrAssign => ">>=",
+    }
+}
+
+fn is_bitwise(op: InfixOp) -> bool {
+    matches!(
+        op,
+        InfixOp::BitAnd
+            | InfixOp::BitOr
+            | InfixOp::BitXor
+            | InfixOp::Shl
+            | InfixOp::Shr
+            | InfixOp::BitAndAssign
+            | InfixOp::BitOrAssign
+            | InfixOp::BitXorAssign
+            | InfixOp::ShlAssign
+            | InfixOp::ShrAssign
+    )
+}
+
+fn int_range(ty: &Type) -> (i128, i128) {
+    match ty {
+        Type::I8 => (i8::MIN.into(), i8::MAX.into()),
+        Type::I16 => (i16::MIN.into(), i16::MAX.into()),
+        Type::I32 => (i32::MIN.into(), i32::MAX.into()),
+        Type::Int => (i64::MIN.into(), i64::MAX.into()),
+        Type::U8 => (0, u8::MAX.into()),
+        Type::U16 => (0, u16::MAX.into()),
+        Type::U32 => (0, u32::MAX.into()),
+        Type::U64 => (0, u64::MAX.into()),
+        _ => unreachable!("int_range on non integer type {}", ty),
+    }
+}
+
+// a constant that left its type's range is a compile error, C would overflow at runtime
+fn fit_const(value: i128, ty: &Type) -> i128 {
+    let (min, max) = int_range(ty);
+    if value < min || value > max {
+        let error_msg = format!("constant expression overflows {}: {} is not in {}..={}", ty, value, min, max);
+        Checker::error_and_exit(&error_msg);
+    }
+    value
+}
+
+// two's complement wrap into ty, what C does for casts and bit flips
+fn wrap_int(value: i128, ty: &Type) -> i128 {
+    let bits = int_bits(ty);
+    let low = value & ((1i128 << bits) - 1);
+    if !ty.is_unsigned() && low >= 1i128 << (bits - 1) {
+        low - (1i128 << bits)
+    } else {
+        low
+    }
+}
+
+fn fold_infix(op: InfixOp, lhs: ConstVal, rhs: ConstVal, int_ty: &Type, ty: Option<&Type>) -> Option<ConstVal> {
+    use ConstVal::*;
+    let val = match (lhs, rhs) {
+        (Int(a), Int(b)) => match op {
+            InfixOp::Add => Int(fit_const(a + b, int_ty)),
+            InfixOp::Sub => Int(fit_const(a - b, int_ty)),
+            InfixOp::Mul => Int(fit_const(a.checked_mul(b).unwrap_or(i128::MAX), int_ty)),
+            InfixOp::Div | InfixOp::Mod if b == 0 => {
+                Checker::error_and_exit("division by zero in a constant expression");
+            }
+            // both truncate toward zero, like C
+            InfixOp::Div => Int(fit_const(a / b, int_ty)),
+            InfixOp::Mod => Int(a % b),
+            InfixOp::BitAnd => Int(a & b),
+            InfixOp::BitOr => Int(a | b),
+            InfixOp::BitXor => Int(a ^ b),
+            InfixOp::Shl | InfixOp::Shr if b < 0 || b as u64 >= int_bits(int_ty) => {
+                let error_msg = format!("shift amount {} is out of range for {}", b, int_ty);
+                Checker::error_and_exit(&error_msg);
+            }
+            InfixOp::Shl if int_ty.is_unsigned() => Int(wrap_int(a << b, int_ty)),
+            InfixOp::Shl => Int(fit_const(a << b, int_ty)),
+            InfixOp::Shr => Int(a >> b),
+            InfixOp::Eq => Bool(a == b),
+            InfixOp::Ne => Bool(a != b),
+            InfixOp::Lt => Bool(a < b),
+            InfixOp::Le => Bool(a <= b),
+            InfixOp::Gt => Bool(a > b),
+            InfixOp::Ge => Bool(a >= b),
+            _ => return None,
+        },
+        // Int and Float mix into a Float
+        (Int(a), Float(b)) => return fold_infix(op, Float(a as f64), Float(b), int_ty, ty),
+        (Float(a), Int(b)) => return fold_infix(op, Float(a), Float(b as f64), int_ty, ty),
+        (Float(a), Float(b)) => match op {
+            InfixOp::Add => Float(a + b),
+            InfixOp::Sub => Float(a - b),
+            InfixOp::Mul => Float(a * b),
+            InfixOp::Div => Float(a / b),
+            InfixOp::Eq => Bool(a == b),
+            InfixOp::Ne => Bool(a != b),
+            InfixOp::Lt => Bool(a < b),
+            InfixOp::Le => Bool(a <= b),
+            InfixOp::Gt => Bool(a > b),
+            InfixOp::Ge => Bool(a >= b),
+            _ => return None,
+        },
+        (Bool(a), Bool(b)) => match op {
+            InfixOp::And => Bool(a && b),
+            InfixOp::Or => Bool(a || b),
+            InfixOp::Eq => Bool(a == b),
+            InfixOp::Ne => Bool(a != b),
+            _ => return None,
+        },
+        (Char(a), Char(b)) => match op {
+            InfixOp::Eq => Bool(a == b),
+            InfixOp::Ne => Bool(a != b),
+            InfixOp::Lt => Bool(a < b),
+            InfixOp::Le => Bool(a <= b),
+            InfixOp::Gt => Bool(a > b),
+            InfixOp::Ge => Bool(a >= b),
+            _ => return None,
+        },
+        (Str(a), Str(b)) => match op {
+            InfixOp::Eq => Bool(a == b),
+            InfixOp::Ne => Bool(a != b),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if let (Float(v), Some(Type::F32)) = (&val, ty) {
+        return Some(Float(*v as f32 as f64));
+    }
+    Some(val)
+}
+
+// `value as target` at compile time, with the same wrapping and truncation as the C cast
+fn fold_cast(value: ConstVal, target: &Type) -> Option<ConstVal> {
+    let val = match (value, target) {
+        (ConstVal::Int(v), t) if t.is_integer() => ConstVal::Int(wrap_int(v, t)),
+        (ConstVal::Bool(b), t) if t.is_integer() => ConstVal::Int(b as i128),
+        (ConstVal::Char(c), t) if t.is_integer() => ConstVal::Int(wrap_int(c as i128, t)),
+        (ConstVal::Float(v), t) if t.is_integer() => {
+            let (min, max) = int_range(t);
+            let v = v.trunc();
+            if !(v >= min as f64 && v <= max as f64) {
+                let error_msg = format!("constant {} does not fit in {}", v, t);
+                Checker::error_and_exit(&error_msg);
+            }
+            ConstVal::Int(v as i128)
+        }
+        (ConstVal::Int(v), Type::F32) => ConstVal::Float(v as f32 as f64),
+        (ConstVal::Float(v), Type::F32) => ConstVal::Float(v as f32 as f64),
+        (ConstVal::Bool(b), Type::F32) => ConstVal::Float(b as u8 as f64),
+        (ConstVal::Int(v), t) if t.is_float() => ConstVal::Float(v as f64),
+        (ConstVal::Float(v), t) if t.is_float() => ConstVal::Float(v),
+        (ConstVal::Bool(b), t) if t.is_float() => ConstVal::Float(b as u8 as f64),
+        (ConstVal::Int(v), Type::Char) => ConstVal::Char(char::from_u32(v as u32)?),
+        _ => return None,
+    };
+    Some(val)
+}
+
+// the same text as format_double() in the C prelude: the shortest digits that read back as
+// the same value, with an exponent outside 1e-5 .. 1e17. `digits` is 17 for Float, 9 for F32
+fn format_float(v: f64, digits: usize) -> String {
+    if v.is_nan() {
+        return "nan".to_string();
+    }
+    if v.is_infinite() {
+        return if v < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let reads_back = |text: &str| {
+        let back: f64 = text.parse().unwrap();
+        if digits < 17 { back as f32 == v as f32 } else { back == v }
+    };
+    let prec = (0..digits - 1).find(|prec| reads_back(&format!("{:.*e}", prec, v))).unwrap_or(digits - 1);
+    let sci = format!("{:.*e}", prec, v);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i64 = exp.parse().unwrap();
+    if !(-5..17).contains(&exp) {
+        return format!("{}e{}", mantissa, exp);
+    }
+    let decimals = (prec as i64 - exp).max(1) as usize;
+    format!("{:.*}", decimals, v)
+}
+
+// a folded constant back as a literal the transpiler can emit
+fn const_to_expr(val: ConstVal, ty: &Type) -> Expr {
+    let negate = |rhs: Expr| Expr::Prefix { op: PrefixOp::Neg, rhs: Box::new(rhs) };
+    match val {
+        ConstVal::Int(v) if ty.is_float() => const_to_expr(ConstVal::Float(v as f64), ty),
+        ConstVal::Int(v) if v < 0 => negate(Expr::Int(v.unsigned_abs() as u64)),
+        ConstVal::Int(v) => Expr::Int(v as u64),
+        ConstVal::Float(v) if !v.is_finite() => {
+            let error_msg = format!("constant expression is {}, not a finite number", v);
+            Checker::error_and_exit(&error_msg);
+        }
+        ConstVal::Float(v) if v.is_sign_negative() => negate(Expr::Float(-v)),
+        ConstVal::Float(v) => Expr::Float(v),
+        ConstVal::Bool(b) => Expr::Bool(b),
+        ConstVal::Str(s) => Expr::String(s),
+        ConstVal::Char(c) => Expr::Char(c),
+    }
+}
+
+fn int_bits(ty: &Type) -> u64 {
+    match ty {
+        Type::I8 | Type::U8 => 8,
+        Type::I16 | Type::U16 => 16,
+        Type::I32 | Type::U32 => 32,
+        _ => 64,
+    }
+}
+
+// a literal number that hasn't been given a type yet: 5, 2.5, -3, (7)
+fn is_untyped_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) => true,
+        Expr::Prefix { op: PrefixOp::Neg, rhs } | Expr::Group(rhs) => is_untyped_literal(rhs),
+        _ => false,
+    }
+}
+
+// the value of an integer literal, negated or in parentheses: 300, -1, (7)
+fn int_literal_value(expr: &Expr) -> Option<i128> {
+    match expr {
+        Expr::Int(v) => Some(*v as i128),
+        Expr::Prefix { op: PrefixOp::Neg, rhs } => int_literal_value(rhs).map(|v| -v),
+        Expr::Group(inner) => int_literal_value(inner),
+        _ => None,
+    }
+}
+
+// types C widens to `int` before doing arithmetic on them
+fn is_promoted(ty: &Type) -> bool {
+    matches!(ty, Type::I8 | Type::I16 | Type::U8 | Type::U16)
+}
+
+fn wrap_in_cast(expr: &mut Expr, ty: &Type) {
+    let value = std::mem::replace(expr, Expr::Nil);
+    *expr = Expr::Cast { value: Box::new(value), ty: ty.clone() };
+}
+
+// methods the language provides on String, Array, Map, Set, Option, Result and the runtime structs
+fn builtin_method(ty: &Type, method: &str) -> Option<MethodSig> {
+    let sig = |params: Vec<Type>, ret: Type| Some(MethodSig { receiver: Receiver::Value, params, ret });
+
+    match (ty, method) {
+        (Type::Option(_), "is_some" | "is_none") => sig(vec![], Type::Bool),
+        (Type::Option(inner), "unwrap") => sig(vec![], (**inner).clone()),
+        (Type::Option(inner), "unwrap_or") => sig(vec![(**inner).clone()], (**inner).clone()),
+        (Type::Option(inner), "expect") => sig(vec![Type::String], (**inner).clone()),
+
+        (Type::Result(..), "is_ok" | "is_err") => sig(vec![], Type::Bool),
+        (Type::Result(ok, _), "unwrap") => sig(vec![], (**ok).clone()),
+        (Type::Result(ok, _), "unwrap_or") => sig(vec![(**ok).clone()], (**ok).clone()),
+        (Type::Result(ok, _), "expect") => sig(vec![Type::String], (**ok).clone()),
+        (Type::Result(_, err), "unwrap_err") => sig(vec![], (**err).clone()),
+
+        // lengths and positions count characters, not bytes
+        (Type::String, "len") => sig(vec![], Type::Int),
+        (Type::String, "is_empty") => sig(vec![], Type::Bool),
+        (Type::String, "substring") => sig(vec![Type::Int, Type::Int], Type::String),
+        (Type::String, "find") => sig(vec![Type::String], Type::Option(Box::new(Type::Int))),
+        (Type::String, "contains" | "starts_with" | "ends_with") => sig(vec![Type::String], Type::Bool),
+        (Type::String, "split") => sig(vec![Type::String], Type::Array(Box::new(Type::String))),
+        (Type::String, "trim" | "to_upper" | "to_lower") => sig(vec![], Type::String),
+        (Type::String, "replace") => sig(vec![Type::String, Type::String], Type::String),
+        (Type::String, "repeat") => sig(vec![Type::Int], Type::String),
+        (Type::String, "push_str") => Some(MethodSig { receiver: Receiver::Mut, params: vec![Type::String], ret: Type::Nil }),
+        (Type::String, "chars") => sig(vec![], Type::Array(Box::new(Type::Char))),
+        (Type::String, "bytes") => sig(vec![], Type::Array(Box::new(Type::U8))),
+
+        (Type::Array(elem), "join") if **elem == Type::String => sig(vec![Type::String], Type::String),
+        (Type::Array(_), "len") => sig(vec![], Type::Int),
+        (Type::Array(_), "is_empty") => sig(vec![], Type::Bool),
+        (Type::Array(elem), "push") => Some(MethodSig { receiver: Receiver::Mut, params: vec![(**elem).clone()], ret: Type::Nil }),
+        (Type::Map(..) | Type::Set(_), "len") => sig(vec![], Type::Int),
+        (Type::Map(..) | Type::Set(_), "is_empty") => sig(vec![], Type::Bool),
+        (Type::Map(key, _) | Type::Set(key), "contains") => sig(vec![(**key).clone()], Type::Bool),
+        (Type::Map(key, value), "insert") => {
+            Some(MethodSig { receiver: Receiver::Mut, params: vec![(**key).clone(), (**value).clone()], ret: Type::Nil })
+        }
+        (Type::Map(key, value), "get") => sig(vec![(**key).clone()], Type::Option(value.clone())),
+        (Type::Map(key, value), "remove") => {
+            Some(MethodSig { receiver: Receiver::Mut, params: vec![(**key).clone()], ret: Type::Option(value.clone()) })
+        }
+        (Type::Map(key, _), "keys") => sig(vec![], Type::Array(key.clone())),
+        (Type::Map(_, value), "values") => sig(vec![], Type::Array(value.clone())),
+        (Type::Set(elem), "insert" | "remove") => {
+            Some(MethodSig { receiver: Receiver::Mut, params: vec![(**elem).clone()], ret: Type::Bool })
+        }
+        (Type::Set(elem), "to_array") => sig(vec![], Type::Array(elem.clone())),
+
+        (Type::Custom(name), "next") if name == "LineReader" || name == "DirReader" => {
+            sig(vec![], Type::Option(Box::new(Type::String)))
+        }
+
+        _ => None,
+    }
+}
+
+// structs the runtime library hands out and the math constants, added in
+// front of every program
+const RUNTIME_TYPES: &str = r#"
+/// What went wrong in an fs_ function. kind is one of "not_found",
+/// "permission_denied", "already_exists", "is_directory", "not_directory",
+/// "not_empty" or "other", message says the same for people.
+struct IoError {
+    kind: String,
+    message: String
+}
+
+impl IoError {
+    fn to_string(self) => String {
+        return self.message;
+    }
+}
+
+/// An open file, read with next() one line at a time. See fs_lines().
+struct LineReader {
+    file: Int
+}
+
+/// An open directory, next() gives the name of each entry. See fs_list_dir().
+struct DirReader {
+    dir: Int
+}
+"#;
+
+// functions of the runtime library, the C side is in the transpiler's emit_builtins()
+fn builtin_func(name: &str) -> Option<FuncSig> {
+    let sig = |params: Vec<Type>, ret: Type| Some(FuncSig { params, ret });
+    let result = |ok: Type| Type::Result(Box::new(ok), Box::new(Type::String));
+    let io_result = |ok: Type| Type::Result(Box::new(ok), Box::new(Type::Custom("IoError".to_string())));
+
+    match name {
+        "read_line" => sig(vec![], Type::Option(Box::new(Type::String))),
+        "read_all" => sig(vec![], Type::String),
+        "parse_int" => sig(vec![Type::String], result(Type::Int)),
+        "parse_float" => sig(vec![Type::String], result(Type::Float)),
+        "parse_bool" => sig(vec![Type::String], result(Type::Bool)),
+
+        "env_get" => sig(vec![Type::String], Type::Option(Box::new(Type::String))),
+        "env_set" => sig(vec![Type::String, Type::String], Type::Nil),
+        "exit" => sig(vec![Type::Int], Type::Nil),
+
+        "fs_read" => sig(vec![Type::String], io_result(Type::String)),
+        "fs_write" | "fs_append" => sig(vec![Type::String, Type::String], io_result(Type::Nil)),
+        "fs_exists" => sig(vec![Type::String], Type::Bool),
+        "fs_remove" => sig(vec![Type::String], io_result(Type::Nil)),
+        "fs_lines" => sig(vec![Type::String], io_result(Type::Custom("LineReader".to_string()))),
+        "fs_list_dir" => sig(vec![Type::String], io_result(Type::Custom("DirReader".to_string()))),
+
+        // the math module, only reachable through `math.gcd(...)`, a name with a '.'
+        // can't be written directly
+        "math.gcd" => sig(vec![Type::Int, Type::Int], Type::Int),
+        "math.sqrt" | "math.cbrt" | "math.exp" | "math.log" | "math.log2" | "math.log10" | "math.sin"
+        | "math.cos" | "math.tan" | "math.asin" | "math.acos" | "math.atan" | "math.floor" | "math.ceil"
+        | "math.round" | "math.trunc" => sig(vec![Type::Float], Type::Float),
+        "math.pow" | "math.atan2" | "math.hypot" => sig(vec![Type::Float, Type::Float], Type::Float),
+        _ => None,
+    }
+}
+
+// math.checked_add and co, typed by their arguments in check_expr
+fn is_int_arith(name: &str) -> bool {
+    let Some(rest) = name.strip_prefix("math.") else {
+        return false;
+    };
+    let op = ["checked_", "wrapping_", "saturating_"]
+        .iter()
+        .find_map(|kind| rest.strip_prefix(kind));
+    matches!(op, Some("add" | "sub" | "mul"))
+}
+
+// every function of the math module, `name` is the "math.sqrt" form
+fn is_math_func(name: &str) -> bool {
+    matches!(name, "math.abs" | "math.min" | "math.max" | "math.clamp")
+        || is_int_arith(name)
+        || (name.starts_with("math.") && builtin_func(name).is_some())
+}
+
+// the Float functions of math are libm's, `name` is the C name (math_sqrt)
+fn needs_libm(name: &str) -> bool {
+    name.strip_prefix("math_")
+        .and_then(|func| builtin_func(&format!("math.{}", func)))
+        .is_some_and(|sig| sig.ret == Type::Float)
+}
+
+// binds type parameters in `param` against the concrete `actual` type
+fn unify(param: &Type, actual: &Type, type_params: &[(String, Vec<String>)], bindings: &mut HashMap<String, Type>) -> bool {
+    match (param, actual) {
+        (Type::Custom(name), _) if type_params.iter().any(|(tp, _)| tp == name) => {
+            match bindings.get(name) {
+                Some(bound) => bound == actual,
+                None => {
+                    bindings.insert(name.clone(), actual.clone());
+                    true
+                }
+            }
+        }
+        (Type::Array(param_elem), Type::Array(actual_elem))
+        | (Type::Option(param_elem), Type::Option(actual_elem))
+        | (Type::Set(param_elem), Type::Set(actual_elem)) => {
+            unify(param_elem, actual_elem, type_params, bindings)
+        }
+        (Type::Result(param_ok, param_err), Type::Result(actual_ok, actual_err))
+        | (Type::Map(param_ok, param_err), Type::Map(actual_ok, actual_err)) => {
+            unify(param_ok, actual_ok, type_params, bindings)
+                && unify(param_err, actual_err, type_params, bindings)
+        }
+        (Type::Tuple(param_elems), Type::Tuple(actual_elems)) => {
+            param_elems.len() == actual_elems.len()
+                && param_elems
+                    .iter()
+                    .zip(actual_elems)
+                    .all(|(p, a)| unify(p, a, type_params, bindings))
+        }
+        (Type::Func(param_params, param_ret), Type::Func(actual_params, actual_ret)) => {
+            param_params.len() == actual_params.len()
+                && param_params
+                    .iter()
+                    .zip(actual_params)
+                    .all(|(p, a)| unify(p, a, type_params, bindings))
+                && unify(param_ret, actual_ret, type_params, bindings)
+        }
+        _ => param == actual,
+    }
+}
+
+fn subst_type(ty: &Type, map: &HashMap<String, Type>) -> Type {
+    match ty {
+        Type::Custom(name) => map.get(name).cloned().unwrap_or_else(|| ty.clone()),
+        Type::Array(elem) => Type::Array(Box::new(subst_type(elem, map))),
+        Type::Option(inner) => Type::Option(Box::new(subst_type(inner, map))),
+        Type::Result(ok, err) => Type::Result(Box::new(subst_type(ok, map)), Box::new(subst_type(err, map))),
+        Type::Func(params, ret) => Type::Func(
+            params.iter().map(|param| subst_type(param, map)).collect(),
+            Box::new(subst_type(ret, map)),
+        ),
+        Type::Tuple(elems) => Type::Tuple(elems.iter().map(|elem| subst_type(elem, map)).collect()),
+        Type::Map(key, value) => Type::Map(Box::new(subst_type(key, map)), Box::new(subst_type(value, map))),
+        Type::Set(elem) => Type::Set(Box::new(subst_type(elem, map))),
+        other => other.clone(),
+    }
+}
+
+fn subst_block(block: &mut Block, map: &HashMap<String, Type>) {
+    for stmt in block {
+        subst_stmt(stmt, map);
+    }
+}
+
+// replace type names (generic parameters, `Self`) everywhere a type is written
+fn subst_stmt(stmt: &mut Stmt, map: &HashMap<String, Type>) {
+    match stmt {
+        Stmt::Let { valuetype, value, .. } => {
+            if let Some(valuetype) = valuetype {
+                *valuetype = subst_type(valuetype, map);
+            }
+            if let Some(value) = value {
+                subst_expr(value, map);
+            }
+        }
+        Stmt::LetTuple { valuetype, value, .. } | Stmt::Const { valuetype, value, .. } => {
+            if let Some(valuetype) = valuetype {
+                *valuetype = subst_type(valuetype, map);
+            }
+            subst_expr(value, map);
+        }
+        Stmt::ExprStmt(expr) | Stmt::Return(Some(expr)) => subst_expr(expr, map),
+        Stmt::While { cond, body } => {
+            subst_expr(cond, map);
+            subst_block(body, map);
+        }
+        Stmt::For { iter, body, ty, .. } => {
+            subst_expr(iter, map);
+            subst_block(body, map);
+            if let Some(ty) = ty {
+                *ty = subst_type(ty, map);
+            }
+        }
+        Stmt::If { cond, then_branch, else_branch } => {
+            subst_expr(cond, map);
+            subst_block(then_branch, map);
+            match else_branch {
+                Some(BlockOrIf::Block(block)) => subst_block(block, map),
+                Some(BlockOrIf::If(nested)) => subst_stmt(nested, map),
+                None => {}
+            }
+        }
+        Stmt::Block(block) => subst_block(block, map),
+        Stmt::Func { params, returntype, body, .. } => {
+            for (_, ty) in params.iter_mut() {
+                *ty = subst_type(ty, map);
+            }
+            *returntype = subst_type(returntype, map);
+            subst_block(body, map);
+        }
+        _ => {}
+    }
+}
+
+fn subst_expr(expr: &mut Expr, map: &HashMap<String, Type>) {
+    match expr {
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::String(_)
+        | Expr::Char(_)
+        | Expr::Nil
+        | Expr::Ident(_) => {}
+        Expr::Interpolation(parts) => {
+            for part in parts {
+                if let InterpPart::Expr(value, _) = part {
+                    subst_expr(value, map);
+                }
+            }
+        }
+        Expr::Prefix { rhs, .. } => subst_expr(rhs, map),
+        Expr::Infix { lhs, rhs, .. } => {
+            subst_expr(lhs, map);
+            subst_expr(rhs, map);
+        }
+        Expr::Call { callee, args } | Expr::ClosureCall { callee, args, .. } => {
+            subst_expr(callee, map);
+            for arg in args {
+                subst_expr(arg, map);
+            }
+        }
+        Expr::MethodCall { target, args, .. } => {
+            subst_expr(target, map);
+            for arg in args {
+                subst_expr(arg, map);
+            }
+        }
+        Expr::Index { target, index, ty } => {
+            subst_expr(target, map);
+            subst_expr(index, map);
+            if let Some(ty) = ty {
+                *ty = subst_type(ty, map);
+            }
+        }
+        Expr::Group(inner)
+        | Expr::FieldAccess { target: inner, .. }
+        | Expr::TupleIndex { target: inner, .. }
+        | Expr::Try { value: inner, .. } => subst_expr(inner, map),
+        Expr::Cast { value, ty } | Expr::ToString { value, ty } => {
+            subst_expr(value, map);
+            *ty = subst_type(ty, map);
+        }
+        Expr::StructLiteral { fields, .. } => {
+            for (_, value) in fields {
+                subst_expr(value, map);
+            }
+        }
+        Expr::ArrayLiteral { elements, ty } => {
+            for elem in elements {
+                subst_expr(elem, map);
+            }
+            if let Some(ty) = ty {
+                *ty = subst_type(ty, map);
+            }
+        }
+        Expr::TupleLiteral { elements, ty } => {
+            for elem in elements {
+                subst_expr(elem, map);
+            }
+            if let Some(ty) = ty {
+                *ty = subst_type(ty, map);
+            }
+        }
+        Expr::Variant { value, .. } => {
+            if let Some(value) = value {
+                subst_expr(value, map);
+            }
+        }
+        Expr::NewCollection { ty } => *ty = subst_type(ty, map),
+        Expr::Closure { params, body, .. } => {
+            for (_, ty) in params.iter_mut() {
+                *ty = subst_type(ty, map);
+            }
+            subst_expr(body, map);
+        }
+    }
+}
+
+// C-identifier-safe spelling of a type, used to name generic instances
+fn mangle(ty: &Type) -> String {
+    match ty {
+        Type::Array(elem) => format!("Array_{}", mangle(elem)),
+        Type::Option(inner) => format!("Option_{}", mangle(inner)),
+        Type::Result(ok, err) => format!("Result_{}_{}", mangle(ok), mangle(err)),
+        Type::Map(key, value) => format!("Map_{}_{}", mangle(key), mangle(value)),
+        Type::Set(elem) => format!("Set_{}", mangle(elem)),
+        Type::Func(params, ret) => {
+            let params: Vec<String> = params.iter().map(mangle).collect();
+            format!("Fn_{}_to_{}", params.join("_"), mangle(ret))
+        }
+        Type::Tuple(elems) => {
+            let elems: Vec<String> = elems.iter().map(mangle).collect();
+            format!("Tuple_{}", elems.join("_"))
+        }
+        other => other.to_string(),
+    }
+}
+
+pub fn check_program(program: &mut Program) {
+    let mut checker = Checker::new();
+    checker.check_program(program);
+}
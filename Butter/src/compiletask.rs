@@ -1,13 +1,15 @@
 use std::process::Command;
 
-pub fn compiletobinary(name: &str, Olevel: i8) {
+// libraries go after the source file, the linker only looks back for symbols
+pub fn compiletobinary(name: &str, olevel: i8, libraries: &[String]) {
     Command::new("gcc")
         .arg(format!("{name}.c"))
-        .arg(format!("-O{Olevel}"))
+        .arg(format!("-O{olevel}"))
         .arg("-march=native")
         .arg("-flto")
         .arg("-o")
-        .arg(format!("{name}"))
+        .arg(name)
+        .args(libraries.iter().map(|lib| format!("-l{lib}")))
         .status()
         .expect("failed to compile");
 
@@ -20,11 +20,20 @@ pub enum TokenKind {
     KwMut, // changeable? like mutable
     KwImport,
     KwArray, // array keyword
+    KwImpl, // methods go in here
+    KwTrait,
+    KwAs, // 5 as U8
+    KwIn, // for c in s
 
     // literals ( Why the hell do they need to call the long word instead of data type s**t )
-    IntLiteral(i64),
+    IntLiteral(u64), // always positive, '-' is a prefix operator
     FloatLiteral(f64),
+    // 200u8 / 1.5f32, the suffix is kept as written
+    SuffixedIntLiteral(u64, String),
+    SuffixedFloatLiteral(f64, String),
     StringLiteral(String),
+    InterpolatedString(Vec<StringPart>), // "Hello {name}"
+    CharLiteral(char), // 'a', '\n', '\u{1F9C8}'
 
     // yeah 'nil' WHY NOT NONE OR NULL laugh over life lang design nerds
     KwNil,
@@ -42,6 +51,18 @@ pub enum TokenKind {
     StarEqual,
     SlashEqual,
 
+    // bitwise
+    Amp,
+    Caret,
+    Tilde,
+    Shl, // <<
+    Shr, // >>, split back into two '>' when closing nested generics
+    AmpEqual,
+    PipeEqual,
+    CaretEqual,
+    ShlEqual,
+    ShrEqual,
+
     EqEq, // ik ik its funny
     BangEq, // been a hell of a ride but i think its my time to grow bang BaNg BANG
     Less,
@@ -52,6 +73,7 @@ pub enum TokenKind {
     Bang,
     AndAnd,
     OrOr,
+    Pipe, // |x: Int| closures
 
     Dot,
     FatArrow, // => "Fat Arrow" these "Naming conventions" are killing me
@@ -64,21 +86,79 @@ pub enum TokenKind {
     Comma,
     Semicolon,
     Colon,
+    Question, // postfix '?' for Option/Result
+
+    // "/// text", the parser hangs it on the next fn/struct/field
+    DocComment(String),
 
     Eof,
 }
 
+// a piece of an interpolated string
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringPart {
+    Lit(String),
+    Expr(Vec<TokenKind>, FormatSpec), // the tokens between '{' and '}' (ending in Eof), then `:spec`
+    Placeholder(FormatSpec), // "{}" or "{:>8}", filled from the arguments of print/format
+}
+
+// what comes after ':' in "{x:>8}" or "{:.2}"
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FormatSpec {
+    pub fill: Option<char>,
+    pub align: Option<Align>,
+    pub zero: bool, // pad numbers with zeros after the sign: {:05}
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Align {
+    Left,   // <
+    Right,  // >
+    Center, // ^
+}
+
 pub struct Lexer {
     chars: Vec<char>,
     pos: usize,
     len: usize,
+    // line of chars[0], holes and multi-line strings are lexed on their own
+    first_line: usize,
 }
 
 impl Lexer {
     pub fn new(src: &str) -> Self {
         let chars: Vec<char> = src.chars().collect();
         let len = chars.len();
-        Self { chars, pos: 0, len }
+        Self { chars, pos: 0, len, first_line: 1 }
+    }
+
+    // lexes a piece of this file that starts at `start`, so errors still report the right line
+    fn sub_lexer(&self, src: &str, start: usize) -> Lexer {
+        let mut lexer = Lexer::new(src);
+        lexer.first_line = self.line_at(start);
+        lexer
+    }
+
+    fn tokens(mut self) -> Vec<TokenKind> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let tok = self.next_token();
+            let done = matches!(tok, TokenKind::Eof);
+            tokens.push(tok);
+            if done {
+                break;
+            }
+        }
+
+        tokens
+    }
+
+    fn error_and_exit(msg: &str) -> ! {
+        eprintln!("\x1b[31m[BUTTER COMPILER ERROR]\x1b[0m {}", msg);
+        std::process::exit(1);
     }
 
     fn is_eof(&self) -> bool {
@@ -93,6 +173,7 @@ impl Lexer {
         self.chars.get(self.pos + 1).copied()
     }
 
+    #[allow(dead_code)]
     fn bump(&mut self) -> Option<char> {
         let ch = self.curr()?;
         self.pos += 1;
@@ -119,6 +200,39 @@ impl Lexer {
         }
     }
 
+    fn skip_block_comment(&mut self) {
+        // assumes we've just seen "/*", block comments nest
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.curr(), self.next()) {
+                (None, _) => Self::error_and_exit("Unterminated block comment"),
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.pos += 2;
+                }
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.pos += 2;
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn lex_doc_comment(&mut self) -> TokenKind {
+        // assumes we've just seen "///"
+        let start = self.pos;
+        while let Some(c) = self.curr() {
+            if c == '\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        let text = text.strip_prefix(' ').unwrap_or(&text);
+        TokenKind::DocComment(text.trim_end().to_string())
+    }
+
     fn ident_or_kw(&mut self) -> TokenKind {
         let start = self.pos;
         self.pos += 1;
@@ -151,84 +265,436 @@ impl Lexer {
             "import" => TokenKind::KwImport,
             "nil"    => TokenKind::KwNil,
             "Array"  => TokenKind::KwArray,
+            "impl"   => TokenKind::KwImpl,
+            "trait"  => TokenKind::KwTrait,
+            "as"     => TokenKind::KwAs,
+            "in"     => TokenKind::KwIn,
             _        => TokenKind::Ident(text),
         }
     }
 
     fn lex_number(&mut self) -> TokenKind {
         let start = self.pos;
-        self.pos += 1;
 
+        // 0xFF, 0b1010, 0o755
+        let radix = match (self.curr(), self.next()) {
+            (Some('0'), Some('x')) => 16,
+            (Some('0'), Some('b')) => 2,
+            (Some('0'), Some('o')) => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.pos += 2;
+            let digits = self.lex_digits(radix);
+            let text: String = self.chars[start..self.pos].iter().collect();
+            if digits.is_empty() {
+                let error_msg = format!("number literal '{}' has no digits after its prefix", text);
+                Self::error_and_exit(&error_msg);
+            }
+            let v = Self::parse_int_literal(&digits, radix, &text);
+            return match self.lex_number_suffix() {
+                Some(suffix) if suffix.starts_with('f') => {
+                    let error_msg = format!("{} literal '{}' can't have the float suffix '{}'", radix_name(radix), text, suffix);
+                    Self::error_and_exit(&error_msg);
+                }
+                Some(suffix) => TokenKind::SuffixedIntLiteral(v, suffix),
+                None => TokenKind::IntLiteral(v),
+            };
+        }
+
+        let mut text = self.lex_digits(10);
+        let mut is_float = false;
+
+        // `pair.0.1` is two tuple indexes, not the float 0.1
+        let after_dot = start > 0 && self.chars[start - 1] == '.';
+
+        if let (Some('.'), Some(next)) = (self.curr(), self.next())
+            && next.is_ascii_digit()
+            && !after_dot
+        {
+            is_float = true;
+            self.pos += 1;
+            text.push('.');
+            text.push_str(&self.lex_digits(10));
+        }
+
+        // exponent: 6.02e23, 1e-9
+        if let Some('e' | 'E') = self.curr()
+            && !after_dot
+        {
+            let sign = matches!(self.next(), Some('+' | '-'));
+            let first_digit = self.chars.get(self.pos + 1 + sign as usize);
+            if first_digit.is_some_and(|c| c.is_ascii_digit()) {
+                is_float = true;
+                text.push('e');
+                if sign {
+                    text.push(self.chars[self.pos + 1]);
+                }
+                self.pos += 1 + sign as usize;
+                text.push_str(&self.lex_digits(10));
+            }
+        }
+
+        let written: String = self.chars[start..self.pos].iter().collect();
+        let suffix = self.lex_number_suffix();
+        match suffix {
+            Some(suffix) if suffix.starts_with('f') => {
+                TokenKind::SuffixedFloatLiteral(Self::parse_float_literal(&text, &written), suffix)
+            }
+            Some(suffix) => {
+                if is_float {
+                    let error_msg = format!("float literal '{}' can't have the integer suffix '{}'", written, suffix);
+                    Self::error_and_exit(&error_msg);
+                }
+                TokenKind::SuffixedIntLiteral(Self::parse_int_literal(&text, 10, &written), suffix)
+            }
+            None if is_float => TokenKind::FloatLiteral(Self::parse_float_literal(&text, &written)),
+            None => TokenKind::IntLiteral(Self::parse_int_literal(&text, 10, &written)),
+        }
+    }
+
+    // digits of the given radix with `_` separators, returned without the separators.
+    // Stops at the first character that can't continue the number.
+    fn lex_digits(&mut self, radix: u32) -> String {
+        let mut digits = String::new();
         while let Some(c) = self.curr() {
-            if c.is_ascii_digit() {
+            if c == '_' {
+                self.pos += 1;
+            } else if c.is_digit(radix) {
+                digits.push(c);
                 self.pos += 1;
+            } else if radix != 10 && c.is_ascii_digit() {
+                let error_msg = format!("invalid digit '{}' in {} literal", c, radix_name(radix));
+                Self::error_and_exit(&error_msg);
             } else {
                 break;
             }
         }
+        digits
+    }
 
-        let mut is_float = false;
+    fn parse_int_literal(digits: &str, radix: u32, written: &str) -> u64 {
+        match u64::from_str_radix(digits, radix) {
+            Ok(v) => v,
+            Err(_) => {
+                let error_msg = format!("integer literal '{}' is too large, the biggest integer is {}", written, u64::MAX);
+                Self::error_and_exit(&error_msg);
+            }
+        }
+    }
 
-        if let Some('.') = self.curr() {
-            if let Some(next) = self.next() {
-                if next.is_ascii_digit() {
-                    is_float = true;
-                    self.pos += 1;
-                    while let Some(c) = self.curr() {
-                        if c.is_ascii_digit() {
-                            self.pos += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                }
+    fn parse_float_literal(text: &str, written: &str) -> f64 {
+        let v: f64 = text.parse().expect("lexer only collects valid float text");
+        if v.is_infinite() {
+            let error_msg = format!("float literal '{}' is too large to be represented", written);
+            Self::error_and_exit(&error_msg);
+        }
+        v
+    }
+
+    // type suffix right after a number: 255u8, 10i32, 2.5f32
+    fn lex_number_suffix(&mut self) -> Option<String> {
+        const SUFFIXES: [&str; 10] = ["i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64"];
+
+        let mut end = self.pos;
+        while let Some(c) = self.chars.get(end) {
+            if c.is_ascii_alphanumeric() || *c == '_' {
+                end += 1;
+            } else {
+                break;
             }
         }
 
-        let text: String = self.chars[start..self.pos].iter().collect();
-        if is_float {
-            let v: f64 = text.parse().expect("invalid float literal");
-            TokenKind::FloatLiteral(v)
+        let word: String = self.chars[self.pos..end].iter().collect();
+        if SUFFIXES.contains(&word.as_str()) {
+            self.pos = end;
+            Some(word)
         } else {
-            let v: i64 = text.parse().expect("invalid int literal");
-            TokenKind::IntLiteral(v)
+            None
         }
     }
 
     fn lex_string(&mut self) -> TokenKind {
+        if self.next() == Some('"') && self.chars.get(self.pos + 2) == Some(&'"') {
+            return self.lex_multiline_string();
+        }
+        // find the closing quote first, so a missing one is reported as such
+        // and not as whatever the interpolation scanner trips over at the end of the file
+        if !self.has_closing_quote() {
+            let error_msg = format!("Unterminated string literal starting on line {}", self.line_at(self.pos));
+            Self::error_and_exit(&error_msg);
+        }
         self.pos += 1;
+        self.lex_string_body(true).unwrap()
+    }
+
+    // assumes we're on the opening quote
+    fn has_closing_quote(&self) -> bool {
+        self.string_end(self.pos + 1, false).is_some()
+    }
+
+    // index just past the quote closing a string whose body starts at `i`,
+    // a string inside a {hole} has to end on the same line as the hole
+    fn string_end(&self, mut i: usize, in_hole: bool) -> Option<usize> {
+        while let Some(c) = self.chars.get(i) {
+            match c {
+                '\\' => i += 2,
+                '"' => return Some(i + 1),
+                '{' | '}' if self.chars.get(i + 1) == Some(c) => i += 2,
+                '{' => match self.hole_end(i + 1) {
+                    Some(end) => i = end + 1,
+                    // lex_interpolation reports the broken hole, with a better message
+                    None if !in_hole => return Some(i),
+                    None => return None,
+                },
+                '\n' if in_hole => return None,
+                _ => i += 1,
+            }
+        }
+        None
+    }
+
+    // index of the '}' closing a {hole} that starts at `i`, strings and chars inside it
+    // are skipped whole so "{join(xs, ", ")}" works. None when the line ends first
+    fn hole_end(&self, mut i: usize) -> Option<usize> {
+        let mut depth = 1;
+        loop {
+            match self.chars.get(i)? {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                '"' => {
+                    i = self.string_end(i + 1, true)?;
+                    continue;
+                }
+                '\'' => {
+                    i += 1;
+                    while *self.chars.get(i)? != '\'' {
+                        if self.chars[i] == '\n' {
+                            return None;
+                        }
+                        i += if self.chars[i] == '\\' { 2 } else { 1 };
+                    }
+                }
+                '\n' => return None,
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn line_at(&self, pos: usize) -> usize {
+        self.chars[..pos].iter().filter(|c| **c == '\n').count() + self.first_line
+    }
+
+    // escapes and {interpolation} up to the closing quote,
+    // or up to the end of input when `quoted` is false ('"' is then just a character)
+    fn lex_string_body(&mut self, quoted: bool) -> Option<TokenKind> {
         let mut result = String::new();
+        let mut parts = Vec::new();
 
         while let Some(c) = self.curr() {
             self.pos += 1;
             match c {
-                '"' | '\'' => {
-                    return TokenKind::StringLiteral(result);
-                }
+                '"' if quoted => return Some(string_token(result, parts)),
                 '\\' => {
-                    // esc char
-                    let esc = self.curr().expect("unterminated escape");
+                    result.push(self.lex_escape());
+                }
+                // "{{" and "}}" are literal braces
+                '{' | '}' if self.curr() == Some(c) => {
                     self.pos += 1;
-                    let ch = match esc {
-                        'n'  => '\n',
-                        't'  => '\t',
-                        'r'  => '\r',
-                        '\\' => '\\',
-                        '"'  => '"',
-                        '0'  => '\0',
-                        other => {
-                            panic!("Unknown escape sequence: \\{}", other);
-                        }
-                    };
-                    result.push(ch);
+                    result.push(c);
                 }
+                '{' => {
+                    if !result.is_empty() {
+                        parts.push(StringPart::Lit(std::mem::take(&mut result)));
+                    }
+                    parts.push(self.lex_interpolation());
+                }
+                '}' => Self::error_and_exit("Unmatched '}' in string literal, write '}}' for a literal brace"),
                 other => {
                     result.push(other);
                 }
             }
         }
 
-        panic!("Unterminated string literal");
+        if quoted {
+            return None;
+        }
+        Some(string_token(result, parts))
+    }
+
+    // """
+    //     SELECT *
+    //     FROM users
+    //     """
+    // the common indentation is stripped, escapes and {interpolation} work as usual
+    fn lex_multiline_string(&mut self) -> TokenKind {
+        self.pos += 3; // skip opening quotes
+        while matches!(self.curr(), Some(' ') | Some('\t') | Some('\r')) {
+            self.pos += 1;
+        }
+        if self.curr() != Some('\n') {
+            Self::error_and_exit("A multi-line string starts on the line after its opening \"\"\"");
+        }
+        self.pos += 1;
+
+        let start = self.pos;
+        loop {
+            match self.curr() {
+                None => Self::error_and_exit("Unterminated multi-line string"),
+                Some('\\') => self.pos += 2, // \" doesn't close the string
+                Some('"') if self.next() == Some('"') && self.chars.get(self.pos + 2) == Some(&'"') => break,
+                _ => self.pos += 1,
+            }
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 3; // skip closing quotes
+
+        let text = strip_indent(&raw);
+        self.sub_lexer(&text, start).lex_string_body(false).unwrap()
+    }
+
+    // r"C:\path" or r#"say "hi""#, no escapes and no interpolation
+    fn lex_raw_string(&mut self) -> TokenKind {
+        self.pos += 1; // skip 'r'
+        let mut hashes = 0;
+        while self.curr() == Some('#') {
+            hashes += 1;
+            self.pos += 1;
+        }
+        self.pos += 1; // skip opening quote
+
+        let start = self.pos;
+        loop {
+            match self.curr() {
+                None => Self::error_and_exit("Unterminated raw string literal"),
+                Some('"') if (1..=hashes).all(|i| self.chars.get(self.pos + i) == Some(&'#')) => break,
+                _ => self.pos += 1,
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 1 + hashes;
+        TokenKind::StringLiteral(text)
+    }
+
+    // r" or r#..#"
+    fn at_raw_string(&self) -> bool {
+        if self.curr() != Some('r') {
+            return false;
+        }
+        let mut i = self.pos + 1;
+        while self.chars.get(i) == Some(&'#') {
+            i += 1;
+        }
+        self.chars.get(i) == Some(&'"')
+    }
+
+    // the `name` in "Hello {name}", assumes we've just seen '{'
+    fn lex_interpolation(&mut self) -> StringPart {
+        let start = self.pos;
+        let Some(end) = self.hole_end(start) else {
+            Self::error_and_exit("Unterminated '{' in string literal, write '{{' for a literal brace")
+        };
+        let src: String = self.chars[start..end].iter().collect();
+        self.pos = end + 1; // skip '}'
+
+        // "{}" / "{:.2}", a value comes from the arguments of print/format
+        if src.is_empty() {
+            return StringPart::Placeholder(FormatSpec::default());
+        }
+        if let Some(spec) = src.strip_prefix(':') {
+            return match parse_format_spec(spec) {
+                Some(spec) => StringPart::Placeholder(spec),
+                None => Self::error_and_exit(&format!("Invalid format spec ':{}', expected something like ':>8' or ':.2'", spec)),
+            };
+        }
+        if src.trim().is_empty() {
+            Self::error_and_exit("Empty '{ }' in string literal, put an expression inside or write '{{}}' for literal braces");
+        }
+
+        // "{price:.2}", only split when what follows the last ':' really is a spec
+        if let Some(colon) = top_level_colon(&src)
+            && let Some(spec) = parse_format_spec(&src[colon + 1..])
+        {
+            return StringPart::Expr(self.sub_lexer(&src[..colon], start).tokens(), spec);
+        }
+        StringPart::Expr(self.sub_lexer(&src, start).tokens(), FormatSpec::default())
+    }
+
+    fn lex_char(&mut self) -> TokenKind {
+        self.pos += 1;
+        let ch = match self.curr() {
+            Some('\\') => {
+                self.pos += 1;
+                self.lex_escape()
+            }
+            Some('\'') => Self::error_and_exit("Empty character literal"),
+            None => Self::error_and_exit("Unterminated character literal"),
+            Some(other) => {
+                self.pos += 1;
+                other
+            }
+        };
+
+        match self.curr() {
+            Some('\'') => {}
+            None => Self::error_and_exit("Unterminated character literal"),
+            Some(_) => Self::error_and_exit("Character literal holds more than one character, use \"...\" for strings"),
+        }
+        self.pos += 1;
+        TokenKind::CharLiteral(ch)
+    }
+
+    // assumes we've just seen the backslash
+    fn lex_escape(&mut self) -> char {
+        let Some(esc) = self.curr() else {
+            Self::error_and_exit("Unterminated escape sequence");
+        };
+        self.pos += 1;
+        match esc {
+            'n'  => '\n',
+            't'  => '\t',
+            'r'  => '\r',
+            '\\' => '\\',
+            '"'  => '"',
+            '\'' => '\'',
+            '0'  => '\0',
+            // \u{1F9C8}
+            'u' => {
+                if self.curr() != Some('{') {
+                    Self::error_and_exit("Expected '{' after \\u");
+                }
+                self.pos += 1;
+                let start = self.pos;
+                while let Some(c) = self.curr() {
+                    if c == '}' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let digits: String = self.chars[start..self.pos].iter().collect();
+                if self.curr() != Some('}') {
+                    Self::error_and_exit("Unterminated \\u{...} escape");
+                }
+                self.pos += 1;
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or_else(|| {
+                        let error_msg = format!("Invalid unicode escape: \\u{{{}}}", digits);
+                        Self::error_and_exit(&error_msg);
+                    })
+            }
+            other => {
+                let error_msg = format!("Unknown escape sequence: \\{}", other);
+                Self::error_and_exit(&error_msg);
+            }
+        }
     }
 
     pub fn next_token(&mut self) -> TokenKind {
@@ -241,6 +707,11 @@ impl Lexer {
 
             let c = self.curr().unwrap();
 
+            // raw string, before identifiers since it starts with 'r'
+            if self.at_raw_string() {
+                return self.lex_raw_string();
+            }
+
             // identifier / keyword
             if c.is_ascii_alphabetic() || c == '_' {
                 return self.ident_or_kw();
@@ -252,19 +723,34 @@ impl Lexer {
             }
 
             // string
-            if c == '"' || c == '\'' {
+            if c == '"' {
                 return self.lex_string();
             }
 
+            // char
+            if c == '\'' {
+                return self.lex_char();
+            }
+
             // the non boomer stuff: operators / punctuation / comments
             match c {
                 // comments: //
                 '/' => {
                     if let Some('/') = self.next() {
-                        // line comment
                         self.pos += 2; // skip "//"
+                        // "///" is a doc comment, "////" is just a line comment again
+                        if self.curr() == Some('/') && self.next() != Some('/') {
+                            self.pos += 1;
+                            return self.lex_doc_comment();
+                        }
+                        // line comment
                         self.skip_line_comment();
                         continue; // restart loop
+                    } else if let Some('*') = self.next() {
+                        // block comment
+                        self.pos += 2; // skip "/*"
+                        self.skip_block_comment();
+                        continue;
                     } else {
                         // / or /=
                         self.pos += 1;
@@ -332,6 +818,14 @@ impl Lexer {
 
                 '<' => {
                     self.pos += 1;
+                    if let Some('<') = self.curr() {
+                        self.pos += 1;
+                        if let Some('=') = self.curr() {
+                            self.pos += 1;
+                            return TokenKind::ShlEqual;
+                        }
+                        return TokenKind::Shl;
+                    }
                     if let Some('=') = self.curr() {
                         self.pos += 1;
                         return TokenKind::LessEq;
@@ -341,6 +835,14 @@ impl Lexer {
 
                 '>' => {
                     self.pos += 1;
+                    if let Some('>') = self.curr() {
+                        self.pos += 1;
+                        if let Some('=') = self.curr() {
+                            self.pos += 1;
+                            return TokenKind::ShrEqual;
+                        }
+                        return TokenKind::Shr;
+                    }
                     if let Some('=') = self.curr() {
                         self.pos += 1;
                         return TokenKind::GreaterEq;
@@ -354,7 +856,11 @@ impl Lexer {
                         self.pos += 1;
                         return TokenKind::AndAnd;
                     }
-                    panic!("single '&' not supported yet");
+                    if let Some('=') = self.curr() {
+                        self.pos += 1;
+                        return TokenKind::AmpEqual;
+                    }
+                    return TokenKind::Amp;
                 }
 
                 '|' => {
@@ -363,7 +869,28 @@ impl Lexer {
                         self.pos += 1;
                         return TokenKind::OrOr;
                     }
-                    panic!("single '|' not supported yet");
+                    // `|x: Int|=> x` is still a closure
+                    if let (Some('='), next) = (self.curr(), self.next())
+                        && next != Some('>')
+                    {
+                        self.pos += 1;
+                        return TokenKind::PipeEqual;
+                    }
+                    return TokenKind::Pipe;
+                }
+
+                '^' => {
+                    self.pos += 1;
+                    if let Some('=') = self.curr() {
+                        self.pos += 1;
+                        return TokenKind::CaretEqual;
+                    }
+                    return TokenKind::Caret;
+                }
+
+                '~' => {
+                    self.pos += 1;
+                    return TokenKind::Tilde;
                 }
 
                 '.' => {
@@ -380,27 +907,261 @@ impl Lexer {
                 ',' => { self.pos += 1; return TokenKind::Comma; }
                 ';' => { self.pos += 1; return TokenKind::Semicolon; }
                 ':' => { self.pos += 1; return TokenKind::Colon; }
+                '?' => { self.pos += 1; return TokenKind::Question; }
 
                 other => {
-                    panic!("Unexpected character in lexer: '{}'", other);
+                    let error_msg = format!("Unexpected character '{}' on line {}", other, self.line_at(self.pos));
+                    Self::error_and_exit(&error_msg);
                 }
             }
         }
     }
 }
 
-pub fn lex(content: &str) -> Vec<TokenKind> {
-    let mut lexer = Lexer::new(content);
-    let mut tokens = Vec::new();
+fn radix_name(radix: u32) -> &'static str {
+    match radix {
+        16 => "hex",
+        8 => "octal",
+        2 => "binary",
+        _ => "decimal",
+    }
+}
+
+// [[fill]align][0][width][.precision], e.g. ">8", "*^10", "05", ".2", "<8.3"
+fn parse_format_spec(spec: &str) -> Option<FormatSpec> {
+    let chars: Vec<char> = spec.chars().collect();
+    let align_of = |c: char| match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+
+    let mut out = FormatSpec::default();
+    let mut i = 0;
+    if chars.len() >= 2 && let Some(align) = align_of(chars[1]) {
+        out.fill = Some(chars[0]);
+        out.align = Some(align);
+        i = 2;
+    } else if let Some(align) = chars.first().and_then(|c| align_of(*c)) {
+        out.align = Some(align);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'0') {
+        out.zero = true;
+        i += 1;
+    }
+
+    let digits = |i: &mut usize| -> Option<usize> {
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        let text: String = chars[start..*i].iter().collect();
+        text.parse().ok()
+    };
+    out.width = digits(&mut i);
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        out.precision = Some(digits(&mut i)?);
+    }
+
+    (i == chars.len()).then_some(out)
+}
 
-    loop {
-        let tok = lexer.next_token();
-        let done = matches!(tok, TokenKind::Eof);
-        tokens.push(tok);
-        if done {
-            break;
+// byte index of the last ':' not nested in (), [], {} or a string
+fn top_level_colon(src: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut found = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in src.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ':' if depth == 0 => found = Some(i),
+            _ => {}
         }
     }
+    found
+}
+
+fn string_token(text: String, mut parts: Vec<StringPart>) -> TokenKind {
+    if parts.is_empty() {
+        return TokenKind::StringLiteral(text);
+    }
+    if !text.is_empty() {
+        parts.push(StringPart::Lit(text));
+    }
+    TokenKind::InterpolatedString(parts)
+}
+
+// drops the indentation shared by every line of a multi-line string, a last line that only
+// holds the closing quotes counts too, and it's dropped along with the newline before it
+fn strip_indent(raw: &str) -> String {
+    let mut lines: Vec<&str> = raw.split('\n').collect();
+    let closing = lines.pop().unwrap_or("");
+    let closing_alone = closing.trim().is_empty();
+    if !closing_alone {
+        lines.push(closing);
+    }
+
+    let indent_of = |line: &str| line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indent_of(line))
+        .chain(closing_alone.then(|| indent_of(closing)))
+        .min()
+        .unwrap_or(0);
+
+    let stripped: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            let cut = indent.min(indent_of(line));
+            line.chars().skip(cut).collect()
+        })
+        .collect();
+    stripped.join("\n")
+}
 
-    tokens
+pub fn lex(content: &str) -> Vec<TokenKind> {
+    Lexer::new(content).tokens()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    fn ident(name: &str) -> TokenKind {
+        Ident(name.to_string())
+    }
+
+    fn string(text: &str) -> TokenKind {
+        StringLiteral(text.to_string())
+    }
+
+    #[test]
+    fn keywords_and_identifiers() {
+        assert_eq!(
+            lex("impl trait as in implement"),
+            vec![KwImpl, KwTrait, KwAs, KwIn, ident("implement"), Eof]
+        );
+    }
+
+    #[test]
+    fn integer_literal_forms() {
+        assert_eq!(
+            lex("0xFF 0b1010 0o755 1_000_000"),
+            vec![IntLiteral(255), IntLiteral(10), IntLiteral(493), IntLiteral(1_000_000), Eof]
+        );
+        assert_eq!(lex("18446744073709551615"), vec![IntLiteral(u64::MAX), Eof]);
+    }
+
+    #[test]
+    fn float_literals_and_exponents() {
+        assert_eq!(lex("6.02e23 1e-9 2.5"), vec![FloatLiteral(6.02e23), FloatLiteral(1e-9), FloatLiteral(2.5), Eof]);
+    }
+
+    #[test]
+    fn suffixed_literals() {
+        assert_eq!(
+            lex("200u8 0b1111_0000u8 2.5f32"),
+            vec![
+                SuffixedIntLiteral(200, "u8".to_string()),
+                SuffixedIntLiteral(0b1111_0000, "u8".to_string()),
+                SuffixedFloatLiteral(2.5, "f32".to_string()),
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn char_literals_and_escapes() {
+        assert_eq!(
+            lex(r"'a' '\n' '\'' '\u{1F9C8}' 'é'"),
+            vec![CharLiteral('a'), CharLiteral('\n'), CharLiteral('\''), CharLiteral('🧈'), CharLiteral('é'), Eof]
+        );
+        assert_eq!(lex(r#""tab\there\0""#), vec![string("tab\there\0"), Eof]);
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        assert_eq!(
+            lex("a & b | c ^ ~d << 2 >> 1 &= |= ^= <<= >>= && ||"),
+            vec![
+                ident("a"), Amp, ident("b"), Pipe, ident("c"), Caret, Tilde, ident("d"), Shl, IntLiteral(2),
+                Shr, IntLiteral(1), AmpEqual, PipeEqual, CaretEqual, ShlEqual, ShrEqual, AndAnd, OrOr, Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_are_skipped_and_doc_comments_kept() {
+        assert_eq!(
+            lex("a // line\n/* block /* nested */ still */ b\n/// doc\n//// not doc\nc"),
+            vec![ident("a"), ident("b"), DocComment("doc".to_string()), ident("c"), Eof]
+        );
+    }
+
+    #[test]
+    fn interpolated_string_segments() {
+        let tokens = lex(r#""Hi {name}, {x:>8} {{not}} {:.2}""#);
+        let [InterpolatedString(parts), Eof] = tokens.as_slice() else {
+            panic!("expected one interpolated string, got {:?}", tokens);
+        };
+        assert_eq!(parts.len(), 6);
+        assert_eq!(parts[0], StringPart::Lit("Hi ".to_string()));
+        assert_eq!(parts[1], StringPart::Expr(vec![ident("name"), Eof], FormatSpec::default()));
+        assert_eq!(parts[2], StringPart::Lit(", ".to_string()));
+        let right8 = FormatSpec { align: Some(Align::Right), width: Some(8), ..FormatSpec::default() };
+        assert_eq!(parts[3], StringPart::Expr(vec![ident("x"), Eof], right8));
+        assert_eq!(parts[4], StringPart::Lit(" {not} ".to_string()));
+        let two_digits = FormatSpec { precision: Some(2), ..FormatSpec::default() };
+        assert_eq!(parts[5], StringPart::Placeholder(two_digits));
+    }
+
+    #[test]
+    fn string_literals_inside_holes() {
+        let tokens = lex(r#""{join(xs, ", ")} {'"'} {"a:b"}""#);
+        let [InterpolatedString(parts), Eof] = tokens.as_slice() else {
+            panic!("expected one interpolated string, got {:?}", tokens);
+        };
+        assert_eq!(parts.len(), 5);
+        let StringPart::Expr(join, _) = &parts[0] else {
+            panic!("expected a hole, got {:?}", parts[0]);
+        };
+        assert!(join.contains(&string(", ")));
+        assert_eq!(parts[2], StringPart::Expr(vec![CharLiteral('"'), Eof], FormatSpec::default()));
+        assert_eq!(parts[4], StringPart::Expr(vec![string("a:b"), Eof], FormatSpec::default()));
+    }
+
+    #[test]
+    fn plain_string_without_braces() {
+        assert_eq!(lex(r#""say \"hi\"""#), vec![string("say \"hi\""), Eof]);
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(lex(r#"r"C:\path\{x}""#), vec![string(r"C:\path\{x}"), Eof]);
+        assert_eq!(lex(r###"r#"she said "hi""#"###), vec![string(r#"she said "hi""#), Eof]);
+    }
+
+    #[test]
+    fn multi_line_strings_drop_shared_indentation() {
+        let src = "\"\"\"\n    SELECT *\n      FROM users\n    \"\"\"";
+        assert_eq!(lex(src), vec![string("SELECT *\n  FROM users"), Eof]);
+    }
 }
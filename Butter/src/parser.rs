@@ -1,12 +1,16 @@
-use crate::lexer::TokenKind;
+use crate::lexer::{FormatSpec, StringPart, TokenKind};
+use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Int(i64),
+    Int(u64), // literals are never negative, `-5` is Neg applied to 5
     Float(f64),
     Bool(bool),
     String(String),
+    // "Hello {name}", lowered to String building calls
+    Interpolation(Vec<InterpPart>),
+    Char(char),
     Nil,
     Ident(String),
 
@@ -26,9 +30,11 @@ pub enum Expr {
         args: Vec<Expr>,
     },
 
+    // `ty` is the array's type, filled in by the checker
     Index {
         target: Box<Expr>,
         index: Box<Expr>,
+        ty: Option<Type>,
     },
 
     Group(Box<Expr>),
@@ -45,13 +51,104 @@ pub enum Expr {
 
     ArrayLiteral {
         elements: Vec<Expr>,
+        ty: Option<Type>,
     },
+
+    // target.method(args), `owner` is the receiver type filled in by the checker
+    MethodCall {
+        target: Box<Expr>,
+        method: String,
+        args: Vec<Expr>,
+        owner: Option<Type>,
+    },
+
+    // some(x) / none / ok(x) / err(e), built by the checker once `ty` is known
+    Variant {
+        variant: Variant,
+        value: Option<Box<Expr>>,
+        ty: Type,
+    },
+
+    // expr? , the checker fills in the operand type and the enclosing function's return type
+    Try {
+        value: Box<Expr>,
+        value_type: Option<Type>,
+        ret_type: Option<Type>,
+    },
+
+    // |x: Int| => x * 2
+    Closure {
+        params: Vec<(String, Type)>,
+        body: Box<Expr>,
+        info: Option<ClosureInfo>,
+    },
+
+    // call through a function value, made from a Call by the checker
+    ClosureCall {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+        fn_type: Type,
+    },
+
+    // (1, "one"), `ty` is filled in by the checker
+    TupleLiteral {
+        elements: Vec<Expr>,
+        ty: Option<Type>,
+    },
+
+    // pair.0
+    TupleIndex {
+        target: Box<Expr>,
+        index: usize,
+    },
+
+    // x as U8, also what suffixed literals like 200u8 parse to
+    Cast {
+        value: Box<Expr>,
+        ty: Type,
+    },
+
+    // built by the checker: the value as a String, the way print shows it
+    ToString {
+        value: Box<Expr>,
+        ty: Type,
+    },
+    // built by the checker from map_new() / set_new(), an empty Map or Set
+    NewCollection {
+        ty: Type,
+    },
+}
+
+// what the checker learns about a closure, needed to lift it into a C function
+#[derive(Debug, Clone)]
+pub struct ClosureInfo {
+    pub name: String,
+    pub captures: Vec<(String, Type)>,
+    pub ret: Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Some,
+    None,
+    Ok,
+    Err,
+}
+
+// one piece of an Expr::Interpolation
+#[derive(Debug, Clone)]
+pub enum InterpPart {
+    Lit(String),
+    Expr(Expr, FormatSpec),
+    // "{}" in print("x = {}", x), the checker swaps it for the argument
+    Placeholder(FormatSpec),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum PrefixOp {
     Neg,
     Not,
+    BitNot, // ~x
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -70,22 +167,51 @@ pub enum InfixOp {
     And,
     Or,
 
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+
     Assign,
     AddAssign,
     SubAssign,
     MulAssign,
     DivAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+    ShlAssign,
+    ShrAssign,
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Stmt {
     Let {
         name: String,
         mutable: bool,
-        valuetype: Type,
+        // None when the type is left to the checker: `let x = 5;`
+        valuetype: Option<Type>,
         value: Option<Expr>,
     },
 
+    // const MAX: Int = 10 * 10;   the value is worked out by the checker
+    Const {
+        name: String,
+        valuetype: Option<Type>,
+        value: Expr,
+    },
+
+    // let (q, r): (Int, Int) = divmod(a, b);
+    LetTuple {
+        names: Vec<String>,
+        mutable: bool,
+        valuetype: Option<Type>,
+        value: Expr,
+    },
+
+    #[allow(dead_code)] // imports are spliced in by the loader in butter.rs
     Import {
         name: String,
     },
@@ -97,6 +223,9 @@ pub enum Stmt {
     Struct {
         name: String,
         fields: Vec<(String, Type)>,
+        // `///` lines above the struct, and above each field (same order as `fields`)
+        docs: Vec<String>,
+        field_docs: Vec<Vec<String>>,
     },
 
     While {
@@ -104,6 +233,16 @@ pub enum Stmt {
         body: Block,
     },
 
+    // for c in "héllo" { ... } walks a String one Char at a time,
+    // for x in arr { ... } an Array (a Map or Set is looped over as an Array)
+    For {
+        var: String,
+        iter: Expr,
+        body: Block,
+        // String or Array<T>, filled in by the checker
+        ty: Option<Type>,
+    },
+
     If {
         cond: Expr,
         then_branch: Block,
@@ -112,9 +251,26 @@ pub enum Stmt {
 
     Func {
         name: String,
+        // fn log<T: Show>(...) => [("T", ["Show"])]
+        type_params: Vec<(String, Vec<String>)>,
+        receiver: Option<Receiver>,
         params: Vec<(String, Type)>,
         returntype: Type,
         body: Block,
+        docs: Vec<String>, // `///` lines above the fn
+    },
+
+    // impl Person { ... } or impl Show for Person { ... }
+    Impl {
+        target: String,
+        trait_name: Option<String>,
+        methods: Vec<Stmt>,
+    },
+
+    // trait Show { fn show(self) => String; }
+    Trait {
+        name: String,
+        methods: Vec<TraitMethod>,
     },
 
     Out,   // break
@@ -123,16 +279,54 @@ pub enum Stmt {
     Block(Block),
 }
 
+// a method signature inside a trait, it has no body
+#[derive(Debug, Clone)]
+pub struct TraitMethod {
+    pub name: String,
+    pub receiver: Receiver,
+    pub params: Vec<(String, Type)>,
+    pub returntype: Type,
+    #[allow(dead_code)] // traits aren't emitted, kept for tooling
+    pub docs: Vec<String>,
+}
+
+// the `self` parameter of a method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Receiver {
+    Value, // self
+    Mut,   // mut self
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
-    Int,
-    Float,
+    Int,   // also spelled I64
+    Float, // also spelled F64
+    I8,
+    I16,
+    I32,
+    U8, // also spelled Byte
+    U16,
+    U32,
+    U64,
+    F32,
     String,
+    Char, // a Unicode scalar value
     Nil,
     Bool,
     Custom(String),
     // Array type with element type
     Array(Box<Type>),
+    Option(Box<Type>),
+    // Result<T, E>
+    Result(Box<Type>, Box<Type>),
+    // fn(Int, Int) => Int
+    Func(Vec<Type>, Box<Type>),
+    // (Int, String)
+    Tuple(Vec<Type>),
+    // Map<String, Int>, hashed keys to values
+    Map(Box<Type>, Box<Type>),
+    // Set<Int>, hashed values without duplicates
+    Set(Box<Type>),
 }
 
 #[derive(Debug, Clone)]
@@ -146,6 +340,17 @@ pub type Block = Vec<Stmt>;
 #[derive(Debug, Clone)]
 pub struct Program {
     pub stmts: Vec<Stmt>,
+    // Option/Result/... types in use, filled in by the checker so the
+    // transpiler knows which C definitions to generate
+    pub type_instances: Vec<Type>,
+    // every checked Expr::Closure, lifted to top-level C functions by the transpiler
+    pub closures: Vec<Expr>,
+    // structs, arrays, ... that get printed, each gets a generated string_push__T formatter
+    pub display_types: Vec<Type>,
+    // runtime functions that return generated types, see checker::builtin_func
+    pub builtins: Vec<String>,
+    // C libraries the binary is linked against, e.g. "m" when math.sqrt is used
+    pub libraries: Vec<String>,
 }
 
 impl fmt::Display for Program {
@@ -158,6 +363,96 @@ impl fmt::Display for Program {
     }
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // same spelling the user writes in source, used in diagnostics
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::I8 => write!(f, "I8"),
+            Type::I16 => write!(f, "I16"),
+            Type::I32 => write!(f, "I32"),
+            Type::U8 => write!(f, "U8"),
+            Type::U16 => write!(f, "U16"),
+            Type::U32 => write!(f, "U32"),
+            Type::U64 => write!(f, "U64"),
+            Type::F32 => write!(f, "F32"),
+            Type::String => write!(f, "String"),
+            Type::Char => write!(f, "Char"),
+            Type::Nil => write!(f, "nil"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Custom(name) => write!(f, "{}", name),
+            Type::Array(elem) => write!(f, "Array<{}>", elem),
+            Type::Option(inner) => write!(f, "Option<{}>", inner),
+            Type::Result(ok, err) => write!(f, "Result<{}, {}>", ok, err),
+            Type::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            Type::Set(elem) => write!(f, "Set<{}>", elem),
+            Type::Func(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") => {}", ret)
+            }
+            Type::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Type {
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::Int | Type::I8 | Type::I16 | Type::I32 | Type::U8 | Type::U16 | Type::U32 | Type::U64
+        )
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::U8 | Type::U16 | Type::U32 | Type::U64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float | Type::F32)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    // built-in scalar types by name, shared by type annotations and literal suffixes
+    pub fn from_name(name: &str) -> Option<Type> {
+        let ty = match name {
+            "Int" | "I64" => Type::Int,
+            "Float" | "F64" => Type::Float,
+            "I8" => Type::I8,
+            "I16" => Type::I16,
+            "I32" => Type::I32,
+            "U8" | "Byte" => Type::U8,
+            "U16" => Type::U16,
+            "U32" => Type::U32,
+            "U64" => Type::U64,
+            "F32" => Type::F32,
+            "Bool" => Type::Bool,
+            "String" => Type::String,
+            "Char" => Type::Char,
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
 impl fmt::Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -175,11 +470,29 @@ impl fmt::Display for Stmt {
 pub struct Parser {
     tokens: Vec<TokenKind>,
     pos: usize,
+    // inside `if`/`while` conditions `x {` starts the body, not a struct literal
+    no_struct_literal: bool,
+    // doc comments, keyed by the index of the token they sit above
+    docs: HashMap<usize, Vec<String>>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<TokenKind>) -> Self {
-        Self { tokens, pos: 0 }
+        // doc comments are trivia, pull them out so the grammar never sees them
+        let mut docs: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut kept = Vec::with_capacity(tokens.len());
+        for tok in tokens {
+            match tok {
+                TokenKind::DocComment(text) => docs.entry(kept.len()).or_default().push(text),
+                other => kept.push(other),
+            }
+        }
+        Self { tokens: kept, pos: 0, no_struct_literal: false, docs }
+    }
+
+    // the doc comments above the current token, anywhere but fn/struct/field they're dropped
+    fn take_docs(&mut self) -> Vec<String> {
+        self.docs.remove(&self.pos).unwrap_or_default()
     }
 
     fn error_and_exit(msg: &str) -> ! {
@@ -191,6 +504,10 @@ impl Parser {
         self.tokens.get(self.pos).unwrap_or(&TokenKind::Eof)
     }
 
+    fn peek_next(&self) -> &TokenKind {
+        self.tokens.get(self.pos + 1).unwrap_or(&TokenKind::Eof)
+    }
+
     fn is_eof(&self) -> bool {
         matches!(self.peek(), TokenKind::Eof)
     }
@@ -224,6 +541,21 @@ impl Parser {
         }
     }
 
+    // the '>' closing a type's arguments. The lexer reads `>>` in
+    // Option<Array<Int>> as a shift, so take just the first '>' of it.
+    fn expect_type_close(&mut self, msg: &str) {
+        let rest = match self.peek() {
+            TokenKind::Shr => TokenKind::Greater,
+            TokenKind::ShrEqual => TokenKind::GreaterEq,
+            TokenKind::GreaterEq => TokenKind::Equal, // let a: Array<Int>= ...
+            _ => {
+                self.expect(&TokenKind::Greater, msg);
+                return;
+            }
+        };
+        self.tokens[self.pos] = rest;
+    }
+
     fn take_ident(&mut self, msg: &str) -> String {
         match self.bump() {
             TokenKind::Ident(s) => s,
@@ -234,12 +566,17 @@ impl Parser {
         }
     }
 
+    // "u8" -> U8, the lexer only hands out known suffixes
+    fn suffix_type(suffix: &str) -> Type {
+        Type::from_name(&suffix.to_uppercase()).expect("lexer only produces known suffixes")
+    }
+
     fn parse_program(&mut self) -> Program {
         let mut stmts = Vec::new();
         while !self.is_eof() {
             stmts.push(self.parse_decl());
         }
-        Program { stmts }
+        Program { stmts, type_instances: Vec::new(), closures: Vec::new(), display_types: Vec::new(), builtins: Vec::new(), libraries: Vec::new() }
     }
 
     fn parse_decl(&mut self) -> Stmt {
@@ -247,6 +584,8 @@ impl Parser {
             TokenKind::KwFn => self.parse_func(),
             TokenKind::KwLet | TokenKind::KwConst => self.parse_let(),
             TokenKind::KwStruct => self.parse_struct(),
+            TokenKind::KwImpl => self.parse_impl(),
+            TokenKind::KwTrait => self.parse_trait(),
             _ => self.parse_stmt(),
         }
     }
@@ -256,18 +595,76 @@ impl Parser {
         if self.matches(&TokenKind::KwArray) {
             self.expect(&TokenKind::Less, "Expected '<' after 'Array'");
             let elem_type = self.parse_type();
-            self.expect(&TokenKind::Greater, "Expected '>' after array element type");
+            self.expect_type_close("Expected '>' after array element type");
             return Type::Array(Box::new(elem_type));
         }
 
+        // fn(Int, String) => Bool
+        if self.matches(&TokenKind::KwFn) {
+            self.expect(&TokenKind::LParen, "Expected '(' after 'fn' in function type");
+            let mut params = Vec::new();
+            if !matches!(self.peek(), TokenKind::RParen) {
+                loop {
+                    params.push(self.parse_type());
+                    if !self.matches(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+            }
+            self.expect(&TokenKind::RParen, "Expected ')' after function type parameters");
+            self.expect(&TokenKind::FatArrow, "Expected '=>' in function type");
+            let ret = self.parse_type();
+            return Type::Func(params, Box::new(ret));
+        }
+
+        // (Int, String)
+        if self.matches(&TokenKind::LParen) {
+            let mut elems = Vec::new();
+            loop {
+                elems.push(self.parse_type());
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RParen, "Expected ')' after tuple element types");
+            if elems.len() < 2 {
+                Self::error_and_exit("a tuple type needs at least two elements, e.g. (Int, String)");
+            }
+            return Type::Tuple(elems);
+        }
+
         match self.bump() {
             TokenKind::Ident(name) => {
                 match name.as_str() {
-                    "Int" => Type::Int,
-                    "Float" => Type::Float,
-                    "Bool" => Type::Bool,
-                    "String" => Type::String,
-                    other => Type::Custom(other.to_string()),
+                    "Option" => {
+                        self.expect(&TokenKind::Less, "Expected '<' after 'Option'");
+                        let inner = self.parse_type();
+                        self.expect_type_close("Expected '>' after Option type");
+                        Type::Option(Box::new(inner))
+                    }
+                    "Result" => {
+                        self.expect(&TokenKind::Less, "Expected '<' after 'Result'");
+                        let ok = self.parse_type();
+                        self.expect(&TokenKind::Comma, "Expected ',' between Result value and error types");
+                        let err = self.parse_type();
+                        self.expect_type_close("Expected '>' after Result types");
+                        Type::Result(Box::new(ok), Box::new(err))
+                    }
+                    "Map" => {
+                        self.expect(&TokenKind::Less, "Expected '<' after 'Map'");
+                        let key = self.parse_type();
+                        self.expect(&TokenKind::Comma, "Expected ',' between Map key and value types");
+                        let value = self.parse_type();
+                        self.expect_type_close("Expected '>' after Map types");
+                        Type::Map(Box::new(key), Box::new(value))
+                    }
+                    "Set" => {
+                        self.expect(&TokenKind::Less, "Expected '<' after 'Set'");
+                        let elem = self.parse_type();
+                        self.expect_type_close("Expected '>' after Set element type");
+                        Type::Set(Box::new(elem))
+                    }
+                    other => Type::from_name(other).unwrap_or_else(|| Type::Custom(other.to_string())),
                 }
             }
 
@@ -280,13 +677,16 @@ impl Parser {
     }
 
     fn parse_struct(&mut self) -> Stmt {
+        let docs = self.take_docs();
         self.expect(&TokenKind::KwStruct, "expected 'struct'");
         let name = self.take_ident("struct name");
         self.expect(&TokenKind::LBrace, "Expected '{' after struct name");
 
         let mut fields = Vec::new();
+        let mut field_docs = Vec::new();
 
         while !matches!(self.peek(), TokenKind::RBrace | TokenKind::Eof) {
+            field_docs.push(self.take_docs());
             let field_name = self.take_ident("field name");
             self.expect(&TokenKind::Colon, "expected ':' after field name");
             let field_type = self.parse_type();
@@ -300,28 +700,157 @@ impl Parser {
 
         self.expect(&TokenKind::RBrace, "Expected '}' to end a struct");
 
-        Stmt::Struct {name, fields}
+        Stmt::Struct {name, fields, docs, field_docs}
+    }
+
+    fn parse_trait(&mut self) -> Stmt {
+        self.expect(&TokenKind::KwTrait, "expected 'trait'");
+        let name = self.take_ident("trait name");
+        self.expect(&TokenKind::LBrace, "Expected '{' after trait name");
+
+        let mut methods = Vec::new();
+        while !matches!(self.peek(), TokenKind::RBrace | TokenKind::Eof) {
+            let docs = self.take_docs();
+            self.expect(&TokenKind::KwFn, "expected 'fn' in trait body");
+            let method_name = self.take_ident("trait method name");
+            self.expect(&TokenKind::LParen, "expected '(' after trait method name");
+            let receiver = match self.parse_receiver() {
+                Some(receiver) => receiver,
+                None => {
+                    let error_msg = format!("trait method '{}' must take 'self' or 'mut self' as its first parameter", method_name);
+                    Self::error_and_exit(&error_msg);
+                }
+            };
+            let params = if self.matches(&TokenKind::Comma) {
+                self.parse_params()
+            } else {
+                Vec::new()
+            };
+            self.expect(&TokenKind::RParen, "expected ')' after parameters");
+            self.expect(&TokenKind::FatArrow, "Expected '=>' after trait method parameters");
+            let returntype = self.parse_type();
+            self.expect(&TokenKind::Semicolon, "expected ';' after trait method signature");
+
+            methods.push(TraitMethod { name: method_name, receiver, params, returntype, docs });
+        }
+
+        self.expect(&TokenKind::RBrace, "Expected '}' to end a trait");
+
+        Stmt::Trait { name, methods }
+    }
+
+    fn parse_impl(&mut self) -> Stmt {
+        self.expect(&TokenKind::KwImpl, "expected 'impl'");
+        let first = self.take_ident("type name after 'impl'");
+        // impl Trait for Type
+        let (target, trait_name) = if self.matches(&TokenKind::KwFor) {
+            (self.take_ident("type name after 'for'"), Some(first))
+        } else {
+            (first, None)
+        };
+        self.expect(&TokenKind::LBrace, "Expected '{' after impl type name");
+
+        let mut methods = Vec::new();
+        while !matches!(self.peek(), TokenKind::RBrace | TokenKind::Eof) {
+            methods.push(self.parse_method());
+        }
+
+        self.expect(&TokenKind::RBrace, "Expected '}' to end an impl block");
+
+        Stmt::Impl { target, trait_name, methods }
+    }
+
+    fn parse_receiver(&mut self) -> Option<Receiver> {
+        let is_self = |t: &TokenKind| matches!(t, TokenKind::Ident(s) if s == "self");
+
+        if is_self(self.peek()) {
+            self.bump();
+            Some(Receiver::Value)
+        } else if matches!(self.peek(), TokenKind::KwMut) && is_self(self.peek_next()) {
+            self.bump();
+            self.bump();
+            Some(Receiver::Mut)
+        } else {
+            None
+        }
     }
 
     fn parse_func(&mut self) -> Stmt {
-        self.expect(&TokenKind::KwFn, "expected 'fn'");
+        self.parse_fn(false)
+    }
 
-        let name = self.take_ident("function name");
-        self.expect(&TokenKind::LParen, "expected '(' after function name");
+    fn parse_method(&mut self) -> Stmt {
+        self.parse_fn(true)
+    }
 
-        let mut params = Vec::new();
-        if !matches!(self.peek(), TokenKind::RParen) {
-            loop {
-                let param = self.take_ident("parameter name");
-                self.expect(&TokenKind::Colon, "Expected ':' after parameter for type declaration");
-                let paramtype = self.parse_type();
-                params.push((param, paramtype));
+    // <T: Show + Eq, U>
+    fn parse_type_params(&mut self) -> Vec<(String, Vec<String>)> {
+        let mut type_params = Vec::new();
+        if !self.matches(&TokenKind::Less) {
+            return type_params;
+        }
 
-                if !self.matches(&TokenKind::Comma) {
-                    break;
+        loop {
+            let name = self.take_ident("type parameter name");
+            let mut bounds = Vec::new();
+            if self.matches(&TokenKind::Colon) {
+                loop {
+                    bounds.push(self.take_ident("trait bound"));
+                    if !self.matches(&TokenKind::Plus) {
+                        break;
+                    }
                 }
             }
+            type_params.push((name, bounds));
+
+            if !self.matches(&TokenKind::Comma) {
+                break;
+            }
         }
+        self.expect(&TokenKind::Greater, "expected '>' after type parameters");
+
+        type_params
+    }
+
+    fn parse_params(&mut self) -> Vec<(String, Type)> {
+        let mut params = Vec::new();
+        loop {
+            let param = self.take_ident("parameter name");
+            self.expect(&TokenKind::Colon, "Expected ':' after parameter for type declaration");
+            let paramtype = self.parse_type();
+            params.push((param, paramtype));
+
+            if !self.matches(&TokenKind::Comma) {
+                break;
+            }
+        }
+        params
+    }
+
+    fn parse_fn(&mut self, is_method: bool) -> Stmt {
+        let docs = self.take_docs();
+        self.expect(&TokenKind::KwFn, "expected 'fn'");
+
+        let name = self.take_ident("function name");
+        let type_params = self.parse_type_params();
+        self.expect(&TokenKind::LParen, "expected '(' after function name");
+
+        let receiver = self.parse_receiver();
+        if is_method && receiver.is_none() {
+            let error_msg = format!("method '{}' must take 'self' or 'mut self' as its first parameter", name);
+            Self::error_and_exit(&error_msg);
+        }
+        if !is_method && receiver.is_some() {
+            let error_msg = format!("'self' parameter is only allowed on methods inside an impl block (in fn '{}')", name);
+            Self::error_and_exit(&error_msg);
+        }
+
+        let has_params = if receiver.is_some() {
+            self.matches(&TokenKind::Comma)
+        } else {
+            !matches!(self.peek(), TokenKind::RParen)
+        };
+        let params = if has_params { self.parse_params() } else { Vec::new() };
         self.expect(&TokenKind::RParen, "expected ')' after parameters");
 
         self.expect(&TokenKind::FatArrow, "Expected '=>' after function parameter");
@@ -330,7 +859,7 @@ impl Parser {
 
         let body = self.parse_block();
 
-        Stmt::Func { name, params, returntype, body }
+        Stmt::Func { name, type_params, receiver, params, returntype, body, docs }
     }
 
     fn parse_let(&mut self) -> Stmt {
@@ -344,9 +873,19 @@ impl Parser {
             mutable = true;
         }
 
+        if is_const && mutable {
+            Self::error_and_exit("a const can never change, drop the 'mut'");
+        }
+
+        if self.matches(&TokenKind::LParen) {
+            if is_const {
+                Self::error_and_exit("a const can't destructure a tuple, declare each value on its own");
+            }
+            return self.parse_let_tuple(mutable);
+        }
+
         let name = self.take_ident("variable name");
-        self.expect(&TokenKind::Colon, "expected ':' after variable Identifer for type declaration");
-        let valuetype = self.parse_type();
+        let valuetype = self.parse_let_type();
 
         let value = if self.matches(&TokenKind::Equal) {
             Some(self.parse_expr())
@@ -357,12 +896,43 @@ impl Parser {
         self.expect(&TokenKind::Semicolon, "expected ';' after let/const");
 
         if is_const {
-            mutable = false;
+            let Some(value) = value else {
+                let error_msg = format!("const '{}' needs a value", name);
+                Self::error_and_exit(&error_msg);
+            };
+            return Stmt::Const { name, valuetype, value };
         }
 
         Stmt::Let { name, mutable, valuetype, value }
     }
 
+    // the optional `: Type` of a let, inferred from the initializer when missing
+    fn parse_let_type(&mut self) -> Option<Type> {
+        if self.matches(&TokenKind::Colon) {
+            Some(self.parse_type())
+        } else {
+            None
+        }
+    }
+
+    // let (q, r): (Int, Int) = ...;   assumes the '(' has already been consumed
+    fn parse_let_tuple(&mut self, mutable: bool) -> Stmt {
+        let mut names = Vec::new();
+        loop {
+            names.push(self.take_ident("variable name in tuple pattern"));
+            if !self.matches(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RParen, "expected ')' after tuple pattern");
+        let valuetype = self.parse_let_type();
+        self.expect(&TokenKind::Equal, "a tuple pattern needs a value to destructure");
+        let value = self.parse_expr();
+        self.expect(&TokenKind::Semicolon, "expected ';' after let");
+
+        Stmt::LetTuple { names, mutable, valuetype, value }
+    }
+
     fn parse_stmt(&mut self) -> Stmt {
         match self.peek() {
             TokenKind::KwReturn => {
@@ -378,6 +948,8 @@ impl Parser {
 
             TokenKind::KwWhile => self.parse_while(),
 
+            TokenKind::KwFor => self.parse_for(),
+
             TokenKind::KwIf => self.parse_if(),
 
             TokenKind::LBrace => {
@@ -419,14 +991,23 @@ impl Parser {
 
     fn parse_while(&mut self) -> Stmt {
         self.expect(&TokenKind::KwWhile, "expected 'while'");
-        let cond = self.parse_expr();
+        let cond = self.parse_cond();
         let body = self.parse_block();
         Stmt::While { cond, body }
     }
 
+    fn parse_for(&mut self) -> Stmt {
+        self.expect(&TokenKind::KwFor, "expected 'for'");
+        let var = self.take_ident("loop variable after 'for'");
+        self.expect(&TokenKind::KwIn, "expected 'in' after the loop variable");
+        let iter = self.parse_cond();
+        let body = self.parse_block();
+        Stmt::For { var, iter, body, ty: None }
+    }
+
     fn parse_if(&mut self) -> Stmt {
         self.expect(&TokenKind::KwIf, "expected 'if'");
-        let cond = self.parse_expr();
+        let cond = self.parse_cond();
         let then_branch = self.parse_block();
 
         let else_branch = if self.matches(&TokenKind::KwElse) {
@@ -454,6 +1035,21 @@ impl Parser {
         self.parse_assignment()
     }
 
+    fn parse_cond(&mut self) -> Expr {
+        let saved = std::mem::replace(&mut self.no_struct_literal, true);
+        let cond = self.parse_expr();
+        self.no_struct_literal = saved;
+        cond
+    }
+
+    // expression inside brackets, where struct literals are always fine again
+    fn parse_nested_expr(&mut self) -> Expr {
+        let saved = std::mem::replace(&mut self.no_struct_literal, false);
+        let expr = self.parse_expr();
+        self.no_struct_literal = saved;
+        expr
+    }
+
     fn parse_assignment(&mut self) -> Expr {
         let lhs = self.parse_or();
 
@@ -463,6 +1059,11 @@ impl Parser {
             TokenKind::MinusEqual => Some(InfixOp::SubAssign),
             TokenKind::StarEqual  => Some(InfixOp::MulAssign),
             TokenKind::SlashEqual => Some(InfixOp::DivAssign),
+            TokenKind::AmpEqual   => Some(InfixOp::BitAndAssign),
+            TokenKind::PipeEqual  => Some(InfixOp::BitOrAssign),
+            TokenKind::CaretEqual => Some(InfixOp::BitXorAssign),
+            TokenKind::ShlEqual   => Some(InfixOp::ShlAssign),
+            TokenKind::ShrEqual   => Some(InfixOp::ShrAssign),
             _ => None,
         };
 
@@ -530,7 +1131,7 @@ impl Parser {
     }
 
     fn parse_comparison(&mut self) -> Expr {
-        let mut expr = self.parse_term();
+        let mut expr = self.parse_bit_or();
         loop {
             let op = if self.matches(&TokenKind::Less) {
                 Some(InfixOp::Lt)
@@ -545,7 +1146,7 @@ impl Parser {
             };
 
             if let Some(op) = op {
-                let rhs = self.parse_term();
+                let rhs = self.parse_bit_or();
                 expr = Expr::Infix {
                     op,
                     lhs: Box::new(expr),
@@ -558,6 +1159,66 @@ impl Parser {
         expr
     }
 
+    // bitwise operators bind tighter than comparisons, so `x & 1 == 0` means `(x & 1) == 0`
+    fn parse_bit_or(&mut self) -> Expr {
+        let mut expr = self.parse_bit_xor();
+        while self.matches(&TokenKind::Pipe) {
+            let rhs = self.parse_bit_xor();
+            expr = Expr::Infix {
+                op: InfixOp::BitOr,
+                lhs: Box::new(expr),
+                rhs: Box::new(rhs),
+            };
+        }
+        expr
+    }
+
+    fn parse_bit_xor(&mut self) -> Expr {
+        let mut expr = self.parse_bit_and();
+        while self.matches(&TokenKind::Caret) {
+            let rhs = self.parse_bit_and();
+            expr = Expr::Infix {
+                op: InfixOp::BitXor,
+                lhs: Box::new(expr),
+                rhs: Box::new(rhs),
+            };
+        }
+        expr
+    }
+
+    fn parse_bit_and(&mut self) -> Expr {
+        let mut expr = self.parse_shift();
+        while self.matches(&TokenKind::Amp) {
+            let rhs = self.parse_shift();
+            expr = Expr::Infix {
+                op: InfixOp::BitAnd,
+                lhs: Box::new(expr),
+                rhs: Box::new(rhs),
+            };
+        }
+        expr
+    }
+
+    fn parse_shift(&mut self) -> Expr {
+        let mut expr = self.parse_term();
+        loop {
+            let op = if self.matches(&TokenKind::Shl) {
+                InfixOp::Shl
+            } else if self.matches(&TokenKind::Shr) {
+                InfixOp::Shr
+            } else {
+                break;
+            };
+            let rhs = self.parse_term();
+            expr = Expr::Infix {
+                op,
+                lhs: Box::new(expr),
+                rhs: Box::new(rhs),
+            };
+        }
+        expr
+    }
+
     fn parse_term(&mut self) -> Expr {
         let mut expr = self.parse_factor();
         loop {
@@ -583,24 +1244,24 @@ impl Parser {
     }
 
     fn parse_factor(&mut self) -> Expr {
-        let mut expr = self.parse_unary();
+        let mut expr = self.parse_cast();
         loop {
             if self.matches(&TokenKind::Star) {
-                let rhs = self.parse_unary();
+                let rhs = self.parse_cast();
                 expr = Expr::Infix {
                     op: InfixOp::Mul,
                     lhs: Box::new(expr),
                     rhs: Box::new(rhs),
                 };
             } else if self.matches(&TokenKind::Slash) {
-                let rhs = self.parse_unary();
+                let rhs = self.parse_cast();
                 expr = Expr::Infix {
                     op: InfixOp::Div,
                     lhs: Box::new(expr),
                     rhs: Box::new(rhs),
                 };
             } else if self.matches(&TokenKind::Percent) {
-                let rhs = self.parse_unary();
+                let rhs = self.parse_cast();
                 expr = Expr::Infix {
                     op: InfixOp::Mod,
                     lhs: Box::new(expr),
@@ -613,6 +1274,19 @@ impl Parser {
         expr
     }
 
+    // x as U8 as Int, binds tighter than '*' but looser than unary '-'
+    fn parse_cast(&mut self) -> Expr {
+        let mut expr = self.parse_unary();
+        while self.matches(&TokenKind::KwAs) {
+            let ty = self.parse_type();
+            expr = Expr::Cast {
+                value: Box::new(expr),
+                ty,
+            };
+        }
+        expr
+    }
+
     fn parse_unary(&mut self) -> Expr {
         if self.matches(&TokenKind::Bang) {
             let rhs = self.parse_unary();
@@ -630,6 +1304,14 @@ impl Parser {
             };
         }
 
+        if self.matches(&TokenKind::Tilde) {
+            let rhs = self.parse_unary();
+            return Expr::Prefix {
+                op: PrefixOp::BitNot,
+                rhs: Box::new(rhs),
+            };
+        }
+
         self.parse_call()
     }
 
@@ -638,16 +1320,7 @@ impl Parser {
 
         loop {
             if self.matches(&TokenKind::LParen) {
-                let mut args = Vec::new();
-                if !matches!(self.peek(), TokenKind::RParen) {
-                    loop {
-                        args.push(self.parse_expr());
-                        if !self.matches(&TokenKind::Comma) {
-                            break;
-                        }
-                    }
-                }
-                self.expect(&TokenKind::RParen, "expected ')' after arguments");
+                let args = self.parse_args();
                 expr = Expr::Call {
                     callee: Box::new(expr),
                     args,
@@ -656,20 +1329,50 @@ impl Parser {
 
             // index: arr[expr]
             else if self.matches(&TokenKind::LBracket) {
-                let index = self.parse_expr();
+                let index = self.parse_nested_expr();
                 self.expect(&TokenKind::RBracket, "expected ']' after index");
                 expr = Expr::Index {
                     target: Box::new(expr),
                     index: Box::new(index),
+                    ty: None,
                 };
             }
 
-            // field access: expr.field
+            // field access: expr.field, or method call: expr.method(args)
             else if self.matches(&TokenKind::Dot) {
+                // tuple index: expr.0
+                if let TokenKind::IntLiteral(index) = *self.peek() {
+                    self.bump();
+                    expr = Expr::TupleIndex {
+                        target: Box::new(expr),
+                        index: index as usize,
+                    };
+                    continue;
+                }
+
                 let field = self.take_ident("field name after '.'");
-                expr = Expr::FieldAccess {
-                    target: Box::new(expr),
-                    field,
+                if self.matches(&TokenKind::LParen) {
+                    let args = self.parse_args();
+                    expr = Expr::MethodCall {
+                        target: Box::new(expr),
+                        method: field,
+                        args,
+                        owner: None,
+                    };
+                } else {
+                    expr = Expr::FieldAccess {
+                        target: Box::new(expr),
+                        field,
+                    };
+                }
+            }
+
+            // error propagation: expr?
+            else if self.matches(&TokenKind::Question) {
+                expr = Expr::Try {
+                    value: Box::new(expr),
+                    value_type: None,
+                    ret_type: None,
                 };
             }
 
@@ -681,27 +1384,64 @@ impl Parser {
         expr
     }
 
+    // assumes the '(' has already been consumed
+    fn parse_args(&mut self) -> Vec<Expr> {
+        let mut args = Vec::new();
+        if !matches!(self.peek(), TokenKind::RParen) {
+            loop {
+                args.push(self.parse_nested_expr());
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(&TokenKind::RParen, "expected ')' after arguments");
+        args
+    }
+
+    // every `{...}` segment is its own little token stream
+    fn parse_interpolation(parts: Vec<StringPart>) -> Expr {
+        let parts = parts
+            .into_iter()
+            .map(|part| match part {
+                StringPart::Lit(s) => InterpPart::Lit(s),
+                StringPart::Placeholder(spec) => InterpPart::Placeholder(spec),
+                StringPart::Expr(tokens, spec) => {
+                    let mut parser = Parser::new(tokens);
+                    let expr = parser.parse_nested_expr();
+                    if !matches!(parser.peek(), TokenKind::Eof) {
+                        let error_msg = format!("unexpected {:?} inside '{{...}}' in a string", parser.peek());
+                        Self::error_and_exit(&error_msg);
+                    }
+                    InterpPart::Expr(expr, spec)
+                }
+            })
+            .collect();
+        Expr::Interpolation(parts)
+    }
 
     fn parse_primary(&mut self) -> Expr {
         match self.peek().clone() {
             TokenKind::IntLiteral(_) | TokenKind::FloatLiteral(_) | 
-            TokenKind::StringLiteral(_) | TokenKind::KwTrue | 
+            TokenKind::StringLiteral(_) | TokenKind::InterpolatedString(_) | TokenKind::CharLiteral(_) | TokenKind::KwTrue | 
             TokenKind::KwFalse | TokenKind::KwNil | TokenKind::Ident(_) => {
                 match self.bump() {
                     TokenKind::IntLiteral(v) => Expr::Int(v),
                     TokenKind::FloatLiteral(v) => Expr::Float(v),
                     TokenKind::StringLiteral(s) => Expr::String(s),
+                    TokenKind::InterpolatedString(parts) => Self::parse_interpolation(parts),
+                    TokenKind::CharLiteral(c) => Expr::Char(c),
                     TokenKind::KwTrue => Expr::Bool(true),
                     TokenKind::KwFalse => Expr::Bool(false),
                     TokenKind::KwNil => Expr::Nil,
                     TokenKind::Ident(name) => {
-                        if self.matches(&TokenKind::LBrace) {
+                        if !self.no_struct_literal && self.matches(&TokenKind::LBrace) {
                             let mut fields = Vec::new();
 
                             while !matches!(self.peek(), TokenKind::RBrace | TokenKind::Eof) {
                                 let field_name = self.take_ident("field name in struct literal");
                                 self.expect(&TokenKind::Equal, "expected '=' after field name");
-                                let value = self.parse_expr();
+                                let value = self.parse_nested_expr();
 
                                 fields.push((field_name, value));
 
@@ -724,11 +1464,63 @@ impl Parser {
                 }
             }
 
+            // 200u8 is 200 as U8
+            TokenKind::SuffixedIntLiteral(v, suffix) => {
+                self.bump();
+                Expr::Cast {
+                    value: Box::new(Expr::Int(v)),
+                    ty: Self::suffix_type(&suffix),
+                }
+            }
+            TokenKind::SuffixedFloatLiteral(v, suffix) => {
+                self.bump();
+                Expr::Cast {
+                    value: Box::new(Expr::Float(v)),
+                    ty: Self::suffix_type(&suffix),
+                }
+            }
+
+            // (expr) or a tuple (a, b)
             TokenKind::LParen => {
                 self.bump();
-                let expr = self.parse_expr();
-                self.expect(&TokenKind::RParen, "expected ')' after expression");
-                Expr::Group(Box::new(expr))
+                let expr = self.parse_nested_expr();
+                if !self.matches(&TokenKind::Comma) {
+                    self.expect(&TokenKind::RParen, "expected ')' after expression");
+                    return Expr::Group(Box::new(expr));
+                }
+
+                let mut elements = vec![expr];
+                while !matches!(self.peek(), TokenKind::RParen | TokenKind::Eof) {
+                    elements.push(self.parse_nested_expr());
+                    if !self.matches(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&TokenKind::RParen, "expected ')' after tuple elements");
+                if elements.len() < 2 {
+                    Self::error_and_exit("a tuple needs at least two elements, e.g. (1, 2)");
+                }
+                Expr::TupleLiteral { elements, ty: None }
+            }
+
+            // closures: |x: Int, y: Int| => x + y, or || => 42
+            TokenKind::Pipe | TokenKind::OrOr => {
+                let mut params = Vec::new();
+                if self.matches(&TokenKind::Pipe) {
+                    if !self.matches(&TokenKind::Pipe) {
+                        params = self.parse_params();
+                        self.expect(&TokenKind::Pipe, "expected '|' after closure parameters");
+                    }
+                } else {
+                    self.expect(&TokenKind::OrOr, "expected '|' to start a closure");
+                }
+                self.expect(&TokenKind::FatArrow, "expected '=>' after closure parameters");
+                let body = self.parse_nested_expr();
+                Expr::Closure {
+                    params,
+                    body: Box::new(body),
+                    info: None,
+                }
             }
 
             // Array literal [1, 2, 3]
@@ -738,7 +1530,7 @@ impl Parser {
 
                 if !matches!(self.peek(), TokenKind::RBracket) {
                     loop {
-                        elements.push(self.parse_expr());
+                        elements.push(self.parse_nested_expr());
                         if !self.matches(&TokenKind::Comma) {
                             break;
                         }
@@ -746,7 +1538,7 @@ impl Parser {
                 }
 
                 self.expect(&TokenKind::RBracket, "expected ']' after array elements");
-                Expr::ArrayLiteral { elements }
+                Expr::ArrayLiteral { elements, ty: None }
             }
 
             other => {
@@ -761,3 +1553,196 @@ pub fn parse_tokens(tokens: Vec<TokenKind>) -> Program {
     let mut parser = Parser::new(tokens);
     parser.parse_program()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    fn parse(src: &str) -> Vec<Stmt> {
+        parse_tokens(lex(src)).stmts
+    }
+
+    // the value of the single statement in `fn main() => nil { ... }`
+    fn parse_expr(src: &str) -> Expr {
+        let stmts = parse(&format!("fn main() => nil {{ {}; }}", src));
+        match &stmts[..] {
+            [Stmt::Func { body, .. }] => match &body[..] {
+                [Stmt::ExprStmt(expr)] => expr.clone(),
+                other => panic!("expected one expression statement, got {:?}", other),
+            },
+            other => panic!("expected one function, got {:?}", other),
+        }
+    }
+
+    fn parse_type(src: &str) -> Type {
+        match &parse(&format!("let x: {} = 0;", src))[..] {
+            [Stmt::Let { valuetype: Some(ty), .. }] => ty.clone(),
+            other => panic!("expected one typed let, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn impl_block_methods() {
+        let stmts = parse("impl Point { fn len(self) => Int { return 0; } fn grow(mut self, by: Int) => nil {} }");
+        let [Stmt::Impl { target, trait_name: None, methods }] = &stmts[..] else {
+            panic!("expected an impl block, got {:?}", stmts);
+        };
+        assert_eq!(target, "Point");
+        assert!(matches!(&methods[0], Stmt::Func { name, receiver: Some(Receiver::Value), params, .. } if name == "len" && params.is_empty()));
+        assert!(matches!(&methods[1], Stmt::Func { name, receiver: Some(Receiver::Mut), params, .. } if name == "grow" && params.len() == 1));
+    }
+
+    #[test]
+    fn traits_and_bounds() {
+        let stmts = parse(
+            "trait Show { fn show(self) => String; }
+             impl Show for Point { fn show(self) => String { return \"p\"; } }
+             fn log<T: Show>(x: T) => nil {}",
+        );
+        assert!(matches!(&stmts[0], Stmt::Trait { name, methods } if name == "Show" && methods.len() == 1));
+        assert!(matches!(&stmts[1], Stmt::Impl { trait_name: Some(name), .. } if name == "Show"));
+        let Stmt::Func { type_params, params, .. } = &stmts[2] else {
+            panic!("expected a function, got {:?}", stmts[2]);
+        };
+        assert_eq!(type_params, &vec![("T".to_string(), vec!["Show".to_string()])]);
+        assert_eq!(params[0].1, Type::Custom("T".to_string()));
+    }
+
+    #[test]
+    fn generic_types() {
+        assert_eq!(parse_type("Option<Int>"), Type::Option(Box::new(Type::Int)));
+        assert_eq!(
+            parse_type("Result<Array<Int>, String>"),
+            Type::Result(Box::new(Type::Array(Box::new(Type::Int))), Box::new(Type::String))
+        );
+        assert_eq!(
+            parse_type("fn(Int, Float) => Bool"),
+            Type::Func(vec![Type::Int, Type::Float], Box::new(Type::Bool))
+        );
+        assert_eq!(parse_type("(Int, String)"), Type::Tuple(vec![Type::Int, Type::String]));
+        // `>>` closes two type argument lists
+        assert_eq!(
+            parse_type("Option<Option<Char>>"),
+            Type::Option(Box::new(Type::Option(Box::new(Type::Char))))
+        );
+        assert_eq!(
+            parse_type("Map<String, Set<I32>>"),
+            Type::Map(Box::new(Type::String), Box::new(Type::Set(Box::new(Type::I32))))
+        );
+        assert_eq!(parse_type("Byte"), Type::U8);
+        assert_eq!(parse_type("F64"), Type::Float);
+    }
+
+    #[test]
+    fn try_operator() {
+        assert!(matches!(parse_expr("read()?"), Expr::Try { value, .. } if matches!(*value, Expr::Call { .. })));
+    }
+
+    #[test]
+    fn closures() {
+        let Expr::Infix { op: InfixOp::Assign, rhs, .. } = parse_expr("f = |x: Int, y: Int| => x + y") else {
+            panic!("expected an assignment");
+        };
+        let Expr::Closure { params, body, .. } = *rhs else {
+            panic!("expected a closure, got {:?}", rhs);
+        };
+        assert_eq!(params, vec![("x".to_string(), Type::Int), ("y".to_string(), Type::Int)]);
+        assert!(matches!(*body, Expr::Infix { op: InfixOp::Add, .. }));
+    }
+
+    #[test]
+    fn tuples_and_grouping() {
+        assert!(matches!(parse_expr("(1, \"one\")"), Expr::TupleLiteral { elements, .. } if elements.len() == 2));
+        assert!(matches!(parse_expr("(1)"), Expr::Group(_)));
+        assert!(matches!(parse_expr("pair.0"), Expr::TupleIndex { index: 0, .. }));
+        let stmts = parse("fn main() => nil { let (q, r): (Int, Int) = divmod(7, 2); }");
+        let [Stmt::Func { body, .. }] = &stmts[..] else {
+            panic!("expected a function");
+        };
+        assert!(matches!(&body[0], Stmt::LetTuple { names, mutable: false, .. } if names == &["q", "r"]));
+    }
+
+    #[test]
+    fn let_without_a_type_and_globals() {
+        let stmts = parse("let mut count = 0; const LIMIT: Int = 10 * 10;");
+        assert!(matches!(&stmts[0], Stmt::Let { name, mutable: true, valuetype: None, value: Some(_) } if name == "count"));
+        assert!(matches!(&stmts[1], Stmt::Const { name, valuetype: Some(Type::Int), .. } if name == "LIMIT"));
+    }
+
+    #[test]
+    fn suffixed_literals_and_casts() {
+        assert!(matches!(parse_expr("200u8"), Expr::Cast { value, ty: Type::U8 } if matches!(*value, Expr::Int(200))));
+        assert!(matches!(parse_expr("x as F32"), Expr::Cast { ty: Type::F32, .. }));
+    }
+
+    #[test]
+    fn for_loops() {
+        let stmts = parse("fn main() => nil { for c in \"abc\" { skip; } }");
+        let [Stmt::Func { body, .. }] = &stmts[..] else {
+            panic!("expected a function");
+        };
+        assert!(matches!(&body[0], Stmt::For { var, body, .. } if var == "c" && matches!(body[..], [Stmt::Skip])));
+    }
+
+    #[test]
+    fn bitwise_precedence() {
+        // like C: shifts bind tighter than comparisons, & tighter than ^ tighter than |
+        let Expr::Infix { op: InfixOp::BitOr, lhs, rhs } = parse_expr("a | b ^ c & d") else {
+            panic!("expected '|' at the top");
+        };
+        assert!(matches!(*lhs, Expr::Ident(_)));
+        let Expr::Infix { op: InfixOp::BitXor, rhs, .. } = *rhs else {
+            panic!("expected '^' under '|'");
+        };
+        assert!(matches!(*rhs, Expr::Infix { op: InfixOp::BitAnd, .. }));
+
+        assert!(matches!(parse_expr("1 << 2 < 8"), Expr::Infix { op: InfixOp::Lt, lhs, .. } if matches!(*lhs, Expr::Infix { op: InfixOp::Shl, .. })));
+        assert!(matches!(parse_expr("x <<= 1"), Expr::Infix { op: InfixOp::ShlAssign, .. }));
+        assert!(matches!(parse_expr("~x"), Expr::Prefix { op: PrefixOp::BitNot, .. }));
+    }
+
+    #[test]
+    fn doc_comments_attach_to_items() {
+        let stmts = parse("/// A point\nstruct Point {\n    /// across\n    x: Int,\n    y: Int\n}\n/// Area\nfn area() => Int { return 0; }");
+        let Stmt::Struct { docs, field_docs, .. } = &stmts[0] else {
+            panic!("expected a struct");
+        };
+        assert_eq!(docs, &vec!["A point".to_string()]);
+        assert_eq!(field_docs, &vec![vec!["across".to_string()], vec![]]);
+        assert!(matches!(&stmts[1], Stmt::Func { docs, .. } if docs == &["Area"]));
+    }
+
+    #[test]
+    fn interpolation_parses_each_segment() {
+        let Expr::Interpolation(parts) = parse_expr("\"x = {x + 1}, {}\"") else {
+            panic!("expected an interpolation");
+        };
+        assert!(matches!(&parts[0], InterpPart::Lit(text) if text == "x = "));
+        assert!(matches!(&parts[1], InterpPart::Expr(Expr::Infix { op: InfixOp::Add, .. }, _)));
+        assert!(matches!(&parts[3], InterpPart::Placeholder(_)));
+    }
+
+    #[test]
+    fn no_struct_literal_in_conditions() {
+        let stmts = parse("fn main() => nil { if a == b { f(P { x = 1 }); } }");
+        let [Stmt::Func { body, .. }] = &stmts[..] else {
+            panic!("expected a function");
+        };
+        let Stmt::If { cond, then_branch, .. } = &body[0] else {
+            panic!("expected an if, got {:?}", body[0]);
+        };
+        assert!(matches!(cond, Expr::Infix { op: InfixOp::Eq, rhs, .. } if matches!(**rhs, Expr::Ident(_))));
+        let [Stmt::ExprStmt(Expr::Call { args, .. })] = &then_branch[..] else {
+            panic!("expected a call, got {:?}", then_branch);
+        };
+        assert!(matches!(args[0], Expr::StructLiteral { .. }));
+    }
+
+    #[test]
+    fn method_calls_and_field_access() {
+        assert!(matches!(parse_expr("p.move_by(1, 2)"), Expr::MethodCall { method, args, .. } if method == "move_by" && args.len() == 2));
+        assert!(matches!(parse_expr("p.x"), Expr::FieldAccess { field, .. } if field == "x"));
+        assert!(matches!(parse_expr("math.PI"), Expr::FieldAccess { field, .. } if field == "PI"));
+    }
+}
@@ -1,7 +1,10 @@
 use crate::parser::{
-    Program, Stmt, Expr, InfixOp, PrefixOp, Type, Block, BlockOrIf,
+    Program, Stmt, Expr, InfixOp, PrefixOp, Type, Block, BlockOrIf, Variant, ClosureInfo,
+    InterpPart,
 };
+use crate::lexer::{Align, FormatSpec};
 
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write as FmtWrite;
 use std::fs;
 
@@ -15,8 +18,17 @@ fn type_to_c(t: &Type) -> String {
     match t {
         Type::Int       => "int64_t".to_string(),
         Type::Float     => "double".to_string(),
+        Type::I8        => "int8_t".to_string(),
+        Type::I16       => "int16_t".to_string(),
+        Type::I32       => "int32_t".to_string(),
+        Type::U8        => "uint8_t".to_string(),
+        Type::U16       => "uint16_t".to_string(),
+        Type::U32       => "uint32_t".to_string(),
+        Type::U64       => "uint64_t".to_string(),
+        Type::F32       => "float".to_string(),
         Type::Bool      => "bool".to_string(),
         Type::String    => "String".to_string(),
+        Type::Char      => "Char".to_string(),
         Type::Nil       => "void".to_string(),
         Type::Custom(name) => name.clone(),
         // Array types
@@ -24,6 +36,19 @@ fn type_to_c(t: &Type) -> String {
             let elem_c = type_to_c(elem_type);
             format!("Array_{}", elem_c.replace("*", "ptr"))
         }
+        // generated per use by emit_type_def()
+        Type::Option(inner) => format!("Option_{}", type_to_c(inner)),
+        Type::Result(ok, err) => format!("Result_{}_{}", type_to_c(ok), type_to_c(err)),
+        // every function value is a function pointer + environment pair
+        Type::Func(..) => "Closure".to_string(),
+        // one struct per tuple shape, fields are _0, _1, ...
+        Type::Tuple(elems) => {
+            let elems: Vec<String> = elems.iter().map(type_to_c).collect();
+            format!("Tuple_{}", elems.join("_"))
+        }
+        // pointers to the storage, copies share it
+        Type::Map(key, value) => format!("Map_{}_{}", type_to_c(key), type_to_c(value)),
+        Type::Set(elem) => format!("Set_{}", type_to_c(elem)),
     }
 }
 
@@ -36,6 +61,8 @@ fn escape_c_string(s: &str) -> String {
             '\n' => out.push_str("\\n"),
             '\r' => out.push_str("\\r"),
             '\t' => out.push_str("\\t"),
+            // NUL and other control bytes as 3-digit octal, so a digit after them can't extend the escape
+            c if c.is_ascii_control() => write!(out, "\\{:03o}", c as u32).unwrap(),
             _    => out.push(ch),
         }
     }
@@ -45,10 +72,16 @@ fn escape_c_string(s: &str) -> String {
 fn emit_expr(out: &mut String, expr: &Expr) {
     match expr {
         Expr::Int(v) => {
-            write!(out, "{v}").unwrap();
+            if *v > i64::MAX as u64 {
+                // only U64 can hold these, C needs the suffix to read them as unsigned
+                write!(out, "((uint64_t){v}ULL)").unwrap();
+            } else {
+                write!(out, "{v}").unwrap();
+            }
         }
         Expr::Float(v) => {
-            write!(out, "{}", v).unwrap();
+            // Debug keeps a '.' or exponent (1.0, 6.02e23) so C reads it as a double
+            write!(out, "{:?}", v).unwrap();
         }
         Expr::Bool(b) => {
             if *b {
@@ -59,14 +92,37 @@ fn emit_expr(out: &mut String, expr: &Expr) {
         }
         Expr::String(s) => {
             let esc = escape_c_string(s);
-            write!(out, "string_from_literal(\"{}\")", esc).unwrap();
+            // the length is passed along since the text may hold NULs
+            write!(out, "string_from_bytes(\"{}\", {})", esc, s.len()).unwrap();
+        }
+        Expr::Interpolation(parts) => {
+            // ({ String __interp = string_new(); string_push(&__interp, "Hi "); ...; __interp; })
+            out.push_str("({ String __interp = string_new(); ");
+            for part in parts {
+                match part {
+                    InterpPart::Lit(s) => {
+                        write!(out, "string_push_bytes(&__interp, \"{}\", {}); ", escape_c_string(s), s.len()).unwrap();
+                    }
+                    InterpPart::Expr(value, spec) => emit_formatted(out, value, spec),
+                    InterpPart::Placeholder(_) => unreachable!("placeholder left after checking"),
+                }
+            }
+            out.push_str("__interp; })");
+        }
+        Expr::Char(c) => {
+            write!(out, "((Char){})", *c as u32).unwrap();
         }
         Expr::Nil => {
             // rarely used as value; treat as 0/null-ish
-            out.push_str("0");
+            out.push('0');
         }
         Expr::Ident(name) => {
-            out.push_str(name);
+            if name == "self" {
+                // methods receive `self` as a pointer to the struct
+                out.push_str("(*self)");
+            } else {
+                out.push_str(name);
+            }
         }
 
         Expr::Group(inner) => {
@@ -78,13 +134,24 @@ fn emit_expr(out: &mut String, expr: &Expr) {
         Expr::Prefix { op, rhs } => {
             match op {
                 PrefixOp::Neg => {
-                    out.push('-');
-                    emit_expr(out, rhs);
+                    if let Expr::Int(v) = **rhs
+                        && v == 1 << 63
+                    {
+                        // 9223372036854775808 itself doesn't fit in int64_t
+                        out.push_str("INT64_MIN");
+                    } else {
+                        out.push('-');
+                        emit_expr(out, rhs);
+                    }
                 }
                 PrefixOp::Not => {
                     out.push('!');
                     emit_expr(out, rhs);
                 }
+                PrefixOp::BitNot => {
+                    out.push('~');
+                    emit_expr(out, rhs);
+                }
             }
         }
 
@@ -115,6 +182,31 @@ fn emit_expr(out: &mut String, expr: &Expr) {
                     out.push_str(" /= ");
                     emit_expr(out, rhs);
                 }
+                InfixOp::BitAndAssign => {
+                    emit_expr(out, lhs);
+                    out.push_str(" &= ");
+                    emit_expr(out, rhs);
+                }
+                InfixOp::BitOrAssign => {
+                    emit_expr(out, lhs);
+                    out.push_str(" |= ");
+                    emit_expr(out, rhs);
+                }
+                InfixOp::BitXorAssign => {
+                    emit_expr(out, lhs);
+                    out.push_str(" ^= ");
+                    emit_expr(out, rhs);
+                }
+                InfixOp::ShlAssign => {
+                    emit_expr(out, lhs);
+                    out.push_str(" <<= ");
+                    emit_expr(out, rhs);
+                }
+                InfixOp::ShrAssign => {
+                    emit_expr(out, lhs);
+                    out.push_str(" >>= ");
+                    emit_expr(out, rhs);
+                }
 
                 InfixOp::Add => {
                     emit_expr(out, lhs);
@@ -183,6 +275,43 @@ fn emit_expr(out: &mut String, expr: &Expr) {
                     out.push_str(" || ");
                     emit_expr(out, rhs);
                 }
+
+                // parenthesized, C ranks these below comparisons and Butter doesn't
+                InfixOp::BitAnd => {
+                    out.push('(');
+                    emit_expr(out, lhs);
+                    out.push_str(" & ");
+                    emit_expr(out, rhs);
+                    out.push(')');
+                }
+                InfixOp::BitOr => {
+                    out.push('(');
+                    emit_expr(out, lhs);
+                    out.push_str(" | ");
+                    emit_expr(out, rhs);
+                    out.push(')');
+                }
+                InfixOp::BitXor => {
+                    out.push('(');
+                    emit_expr(out, lhs);
+                    out.push_str(" ^ ");
+                    emit_expr(out, rhs);
+                    out.push(')');
+                }
+                InfixOp::Shl => {
+                    out.push('(');
+                    emit_expr(out, lhs);
+                    out.push_str(" << ");
+                    emit_expr(out, rhs);
+                    out.push(')');
+                }
+                InfixOp::Shr => {
+                    out.push('(');
+                    emit_expr(out, lhs);
+                    out.push_str(" >> ");
+                    emit_expr(out, rhs);
+                    out.push(')');
+                }
             }
         }
 
@@ -198,14 +327,14 @@ fn emit_expr(out: &mut String, expr: &Expr) {
             out.push(')');
         }
 
-        Expr::Index { target, index } => {
-            // Arrays need special handling: arr.data[index]
-            // We'll emit as: array_get(target, index)
-            out.push_str("array_get(");
+        Expr::Index { target, index, ty } => {
+            // Array_T__at() checks the bounds and points into the array, so this is also assignable
+            let ty = ty.as_ref().expect("index was not resolved by the checker");
+            write!(out, "(*{}__at(", type_to_c(ty)).unwrap();
             emit_expr(out, target);
             out.push_str(", ");
             emit_expr(out, index);
-            out.push(')');
+            out.push_str("))");
         }
 
         Expr::StructLiteral { name, fields } => {
@@ -227,14 +356,171 @@ fn emit_expr(out: &mut String, expr: &Expr) {
             out.push_str(field);
         }
 
+        Expr::MethodCall { target, method, args, owner } => {
+            let owner = type_to_c(owner.as_ref().expect("method call was not resolved by the checker"));
+            write!(out, "{}__{}(", owner, method).unwrap();
+            if is_place(target) {
+                out.push('&');
+                emit_expr(out, target);
+            } else {
+                // temporaries have no address, so park them in a compound literal
+                write!(out, "({}[]){{", owner).unwrap();
+                emit_expr(out, target);
+                out.push('}');
+            }
+            for arg in args {
+                out.push_str(", ");
+                emit_expr(out, arg);
+            }
+            out.push(')');
+        }
+
+        Expr::Variant { variant, value, ty } => {
+            // (Option_int64_t){ .is_some = true, .value = 5 }
+            write!(out, "({}){{ ", type_to_c(ty)).unwrap();
+            let (tag, field, payload) = match (variant, ty) {
+                (Variant::Some, Type::Option(inner)) => (".is_some = true", "value", &**inner),
+                (Variant::None, _) => (".is_some = false", "value", &Type::Nil),
+                (Variant::Ok, Type::Result(ok, _)) => (".is_ok = true", "value", &**ok),
+                (Variant::Err, Type::Result(_, err)) => (".is_ok = false", "error", &**err),
+                _ => panic!("variant does not match its type {:?}", ty),
+            };
+            out.push_str(tag);
+            if let Some(value) = value
+                && *payload != Type::Nil
+            {
+                write!(out, ", .{} = ", field).unwrap();
+                emit_expr(out, value);
+            }
+            out.push_str(" }");
+        }
+
+        Expr::Try { value, value_type, ret_type } => {
+            // GNU statement expression: bail out of the function early, otherwise unwrap
+            let value_type = value_type.as_ref().expect("'?' was not resolved by the checker");
+            let ret_c = type_to_c(ret_type.as_ref().expect("'?' was not resolved by the checker"));
+            write!(out, "({{ {} __try = ", type_to_c(value_type)).unwrap();
+            emit_expr(out, value);
+            let payload = match value_type {
+                Type::Option(inner) => {
+                    write!(out, "; if (!__try.is_some) return ({}){{ .is_some = false }};", ret_c).unwrap();
+                    inner
+                }
+                Type::Result(ok, err) => {
+                    write!(out, "; if (!__try.is_ok) return ({}){{ .is_ok = false", ret_c).unwrap();
+                    if **err != Type::Nil {
+                        out.push_str(", .error = __try.error");
+                    }
+                    out.push_str(" };");
+                    ok
+                }
+                other => panic!("'?' on non Option/Result type {:?}", other),
+            };
+            if **payload != Type::Nil {
+                out.push_str(" __try.value;");
+            }
+            out.push_str(" })");
+        }
+
+        Expr::Closure { info, .. } => {
+            let info = info.as_ref().expect("closure was not checked");
+            if info.captures.is_empty() {
+                write!(out, "(Closure){{ .fn = (void*){}, .env = NULL }}", info.name).unwrap();
+            } else {
+                // copy the captured values into an arena allocated environment
+                write!(out, "({{ {0}_env* __env = arena_alloc(sizeof({0}_env));", info.name).unwrap();
+                for (cname, _) in &info.captures {
+                    write!(out, " __env->{} = ", cname).unwrap();
+                    emit_expr(out, &Expr::Ident(cname.clone()));
+                    out.push(';');
+                }
+                write!(out, " (Closure){{ .fn = (void*){}, .env = __env }}; }})", info.name).unwrap();
+            }
+        }
+
+        Expr::ClosureCall { callee, args, fn_type } => {
+            let Type::Func(params, ret) = fn_type else {
+                panic!("closure call through non function type {:?}", fn_type);
+            };
+            // ((ret(*)(void*, params...))f.fn)(f.env, args...)
+            out.push_str("({ Closure __fn = ");
+            emit_expr(out, callee);
+            write!(out, "; (({}(*)(void*", type_to_c(ret)).unwrap();
+            for param in params {
+                write!(out, ", {}", type_to_c(param)).unwrap();
+            }
+            out.push_str("))__fn.fn)(__fn.env");
+            for arg in args {
+                out.push_str(", ");
+                emit_expr(out, arg);
+            }
+            out.push_str("); })");
+        }
+
+        Expr::TupleLiteral { elements, ty } => {
+            // (Tuple_int64_t_String){ ._0 = 1, ._1 = ... }
+            let ty = ty.as_ref().expect("tuple literal was not checked");
+            write!(out, "({}){{", type_to_c(ty)).unwrap();
+            for (i, elem) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write!(out, "._{} = ", i).unwrap();
+                emit_expr(out, elem);
+            }
+            out.push('}');
+        }
+
+        Expr::TupleIndex { target, index } => {
+            emit_expr(out, target);
+            write!(out, "._{}", index).unwrap();
+        }
+
+        Expr::Cast { value, ty } => {
+            // the value may be an operator the checker wrapped, keep it together
+            write!(out, "(({})(", type_to_c(ty)).unwrap();
+            emit_expr(out, value);
+            out.push_str("))");
+        }
+
+        Expr::NewCollection { ty } => write!(out, "{}__new()", type_to_c(ty)).unwrap(),
+
+        Expr::ToString { value, ty } => {
+            if *ty == Type::Nil {
+                // there's no C value to format, run it for its side effects
+                if !matches!(**value, Expr::Nil) {
+                    out.push_str("({ ");
+                    emit_expr(out, value);
+                    out.push_str("; string_from_literal(\"nil\"); })");
+                } else {
+                    out.push_str("string_from_literal(\"nil\")");
+                }
+                return;
+            }
+            write!(out, "({{ String __str = string_new(); {}(&__str, ", formatter_name(ty)).unwrap();
+            if *ty == Type::Bool {
+                // `true` is an int in C
+                out.push_str("(bool)");
+            }
+            emit_expr(out, value);
+            out.push_str("); __str; })");
+        }
+
         // Array literal
-        Expr::ArrayLiteral { elements } => {
-            out.push_str("array_from_literal((void*[]){");
+        Expr::ArrayLiteral { elements, ty } => {
+            let ty = ty.as_ref().expect("array literal was not resolved by the checker");
+            let cname = type_to_c(ty);
+            if elements.is_empty() {
+                write!(out, "{}__new()", cname).unwrap();
+                return;
+            }
+            // the elements are copied out of the compound literal into the arena
+            let Type::Array(elem) = ty else { unreachable!("array literal of type {:?}", ty) };
+            write!(out, "{}__from(({}[]){{", cname, type_to_c(elem)).unwrap();
             for (i, elem) in elements.iter().enumerate() {
                 if i > 0 {
                     out.push_str(", ");
                 }
-                out.push_str("(void*)(intptr_t)");
                 emit_expr(out, elem);
             }
             write!(out, "}}, {})", elements.len()).unwrap();
@@ -242,6 +528,18 @@ fn emit_expr(out: &mut String, expr: &Expr) {
     }
 }
 
+// can we take the address of this expression with '&'
+fn is_place(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(_) => true,
+        // (*Array_T__at(arr, i)) points into the elements, wherever arr came from
+        Expr::Index { .. } => true,
+        Expr::FieldAccess { target, .. } | Expr::TupleIndex { target, .. } => is_place(target),
+        Expr::Group(inner) => is_place(inner),
+        _ => false,
+    }
+}
+
 fn emit_block(out: &mut String, block: &Block, level: usize) {
     out.push_str("{\n");
     for stmt in block {
@@ -283,7 +581,7 @@ fn emit_stmt(out: &mut String, stmt: &Stmt, level: usize) {
     match stmt {
         Stmt::Let { name, valuetype, value, .. } => {
             indent(out, level);
-            let cty = type_to_c(valuetype);
+            let cty = type_to_c(valuetype.as_ref().expect("let type was not resolved by the checker"));
             write!(out, "{} {}", cty, name).unwrap();
             if let Some(expr) = value {
                 out.push_str(" = ");
@@ -292,6 +590,32 @@ fn emit_stmt(out: &mut String, stmt: &Stmt, level: usize) {
             out.push_str(";\n");
         }
 
+        Stmt::Const { name, valuetype, value } => {
+            // the checker already folded the value into a literal
+            indent(out, level);
+            let cty = type_to_c(valuetype.as_ref().expect("const type was not resolved by the checker"));
+            write!(out, "const {} {} = ", cty, name).unwrap();
+            emit_expr(out, value);
+            out.push_str(";\n");
+        }
+
+        Stmt::LetTuple { names, valuetype, value, .. } => {
+            // unpack through a temporary: Tuple_x __tuple_q_r = ...; int64_t q = __tuple_q_r._0; ...
+            let valuetype = valuetype.as_ref().expect("let type was not resolved by the checker");
+            let Type::Tuple(elems) = valuetype else {
+                panic!("tuple pattern with non tuple type {:?}", valuetype);
+            };
+            let tmp = format!("__tuple_{}", names.join("_"));
+            indent(out, level);
+            write!(out, "{} {} = ", type_to_c(valuetype), tmp).unwrap();
+            emit_expr(out, value);
+            out.push_str(";\n");
+            for (i, (name, ty)) in names.iter().zip(elems).enumerate() {
+                indent(out, level);
+                writeln!(out, "{} {} = {}._{};", type_to_c(ty), name, tmp, i).unwrap();
+            }
+        }
+
         Stmt::ExprStmt(expr) => {
             indent(out, level);
             emit_expr(out, expr);
@@ -317,6 +641,54 @@ fn emit_stmt(out: &mut String, stmt: &Stmt, level: usize) {
             emit_block(out, body, level);
         }
 
+        Stmt::For { var, iter, body, ty: Some(array @ Type::Array(elem)) } => {
+            indent(out, level);
+            out.push_str("{\n");
+            indent(out, level + 1);
+            write!(out, "{} __iter_{} = ", type_to_c(array), var).unwrap();
+            emit_expr(out, iter);
+            out.push_str(";\n");
+            indent(out, level + 1);
+            // the length when the loop starts, elements pushed by the body aren't visited
+            writeln!(out, "size_t __len_{0} = __iter_{0}->len;", var).unwrap();
+            indent(out, level + 1);
+            write!(out, "for (size_t __pos_{0} = 0; __pos_{0} < __len_{0}; __pos_{0}++) ", var).unwrap();
+            out.push_str("{\n");
+            indent(out, level + 2);
+            writeln!(out, "{1} {0} = __iter_{0}->data[__pos_{0}];", var, type_to_c(elem)).unwrap();
+            for stmt in body {
+                emit_stmt(out, stmt, level + 2);
+            }
+            indent(out, level + 1);
+            out.push_str("}\n");
+            indent(out, level);
+            out.push_str("}\n");
+        }
+
+        Stmt::For { var, iter, body, .. } => {
+            // decode one UTF-8 character per iteration, before the body so `skip` works
+            indent(out, level);
+            out.push_str("{\n");
+            indent(out, level + 1);
+            write!(out, "String __iter_{} = ", var).unwrap();
+            emit_expr(out, iter);
+            out.push_str(";\n");
+            indent(out, level + 1);
+            writeln!(out, "size_t __pos_{} = 0;", var).unwrap();
+            indent(out, level + 1);
+            write!(out, "while (__pos_{0} < __iter_{0}.len) ", var).unwrap();
+            out.push_str("{\n");
+            indent(out, level + 2);
+            writeln!(out, "Char {0} = utf8_decode(__iter_{0}, &__pos_{0});", var).unwrap();
+            for stmt in body {
+                emit_stmt(out, stmt, level + 2);
+            }
+            indent(out, level + 1);
+            out.push_str("}\n");
+            indent(out, level);
+            out.push_str("}\n");
+        }
+
         Stmt::If { cond, then_branch, else_branch } => {
             emit_if(out, cond, then_branch, else_branch, level);
         }
@@ -346,47 +718,1002 @@ fn emit_stmt(out: &mut String, stmt: &Stmt, level: usize) {
             // already emitted as typedef at top-level in emit_structs()
         }
 
-        Stmt::Func { .. } => {
+        Stmt::Func { .. } | Stmt::Impl { .. } => {
             // handled at top-level in emit_functions()
         }
+
+        Stmt::Trait { .. } => {
+            // traits only exist for the checker, calls are already resolved
+        }
     }
 }
 
-fn emit_structs(out: &mut String, prog: &Program) {
+// structs and Option/Result/tuple instances, each after the types it holds by value
+fn emit_types(out: &mut String, prog: &Program) {
+    let mut structs = HashMap::new();
+    for stmt in &prog.stmts {
+        if let Stmt::Struct { name, .. } = stmt {
+            structs.insert(name.as_str(), stmt);
+        }
+    }
+
+    // forward typedefs first, so struct names can be used before their definition
     for stmt in &prog.stmts {
-        if let Stmt::Struct { name, fields } = stmt {
-            write!(out, "typedef struct {} {{\n", name).unwrap();
-            for (fname, ftype) in fields {
+        if let Stmt::Struct { name, .. } = stmt {
+            writeln!(out, "typedef struct {0} {0};", name).unwrap();
+        }
+    }
+    out.push('\n');
+
+    let mut done = HashSet::new();
+    for stmt in &prog.stmts {
+        if let Stmt::Struct { name, .. } = stmt {
+            emit_type_def(out, &Type::Custom(name.clone()), &structs, &mut done);
+        }
+    }
+    for ty in &prog.type_instances {
+        emit_type_def(out, ty, &structs, &mut done);
+    }
+
+    // after every struct is complete, the helpers take elements by value
+    for ty in &prog.type_instances {
+        if let Type::Array(elem) = ty {
+            emit_array_helpers(out, &type_to_c(ty), elem);
+        }
+    }
+
+    // maps and sets come after arrays, keys() hands out an Array
+    let mut hashed = HashSet::new();
+    for ty in &prog.type_instances {
+        if let Type::Map(key, _) | Type::Set(key) = ty {
+            emit_key_funcs(out, key, &structs, &mut hashed);
+        }
+    }
+    for ty in &prog.type_instances {
+        match ty {
+            Type::Map(key, value) => emit_collection_helpers(out, &type_to_c(ty), key, Some(value)),
+            Type::Set(elem) => emit_collection_helpers(out, &type_to_c(ty), elem, None),
+            _ => {}
+        }
+    }
+}
+
+// key_hash__T and key_eq__T for a map key or set element type, the ones for
+// struct fields and tuple elements first
+fn emit_key_funcs(out: &mut String, key: &Type, structs: &HashMap<&str, &Stmt>, done: &mut HashSet<String>) {
+    let kc = type_to_c(key);
+    if !done.insert(kc.clone()) {
+        return;
+    }
+    let parts: Vec<(String, Type)> = match key {
+        Type::Custom(name) => match structs.get(name.as_str()) {
+            Some(Stmt::Struct { fields, .. }) => fields.clone(),
+            _ => unreachable!("map key struct '{}' was not declared", name),
+        },
+        Type::Tuple(elems) => elems.iter().enumerate().map(|(i, elem)| (format!("_{}", i), elem.clone())).collect(),
+        _ => Vec::new(),
+    };
+    for (_, part) in &parts {
+        emit_key_funcs(out, part, structs, done);
+    }
+
+    let (hash, eq) = match key {
+        Type::String => (
+            "return hash_bytes(x.data, x.len);".to_string(),
+            "return a.len == b.len && memcmp(a.data, b.data, a.len) == 0;".to_string(),
+        ),
+        Type::Custom(_) | Type::Tuple(_) => {
+            let mut hash = "uint64_t h = 0;".to_string();
+            let mut eq = "return true".to_string();
+            for (field, fty) in &parts {
+                let fc = type_to_c(fty);
+                write!(hash, " h = hash_combine(h, key_hash__{}(x.{}));", fc, field).unwrap();
+                write!(eq, " && key_eq__{0}(a.{1}, b.{1})", fc, field).unwrap();
+            }
+            hash.push_str(" return h;");
+            eq.push(';');
+            (hash, eq)
+        }
+        _ => ("return hash_int((uint64_t)x);".to_string(), "return a == b;".to_string()),
+    };
+    writeln!(out, "uint64_t key_hash__{0}({0} x) {{ {1} }}", kc, hash).unwrap();
+    writeln!(out, "bool key_eq__{0}({0} a, {0} b) {{ {1} }}", kc, eq).unwrap();
+    out.push('\n');
+}
+
+// Map_K_V and Set_T are pointers to arena storage, so copies see each
+// other's changes. `value` is None for a set. Removing an entry moves the
+// last one into its place, otherwise entries stay in insertion order.
+fn emit_collection_helpers(out: &mut String, cname: &str, key: &Type, value: Option<&Type>) {
+    let kc = type_to_c(key);
+    let arr_k = type_to_c(&Type::Array(Box::new(key.clone())));
+    writeln!(
+        out,
+        "{0} {0}__new(void) {{ {0} self = arena_alloc(sizeof(struct {0})); *self = (struct {0}){{ 0 }}; self->slots = hash_slots_resize(NULL, &self->slot_count, 0); return self; }}",
+        cname
+    ).unwrap();
+    // the slot holding `key`, or the free slot it would go in
+    writeln!(
+        out,
+        "size_t {0}__find({0} self, {1} key, uint64_t hash) {{ size_t mask = self->slot_count - 1, at = hash & mask; while (self->slots[at].entry >= 0 && !(self->slots[at].hash == hash && key_eq__{1}(self->keys[self->slots[at].entry], key))) at = (at + 1) & mask; return at; }}",
+        cname, kc
+    ).unwrap();
+    writeln!(out, "int64_t {0}__len({0} *self) {{ return (int64_t)(*self)->len; }}", cname).unwrap();
+    writeln!(out, "bool {0}__is_empty({0} *self) {{ return (*self)->len == 0; }}", cname).unwrap();
+    writeln!(
+        out,
+        "bool {0}__contains({0} *self, {1} key) {{ return (*self)->slots[{0}__find(*self, key, key_hash__{1}(key))].entry >= 0; }}",
+        cname, kc
+    ).unwrap();
+
+    // insert: look the key up, then append a new entry and grow the table if needed
+    let lookup = format!("{0} m = *self; uint64_t hash = key_hash__{1}(key); size_t at = {0}__find(m, key, hash);", cname, kc);
+    let append = |value_c: Option<&str>| {
+        let mut grow = String::from("if (m->len == m->cap) { ");
+        match value_c {
+            Some(vc) => write!(
+                grow,
+                "size_t cap = m->cap; m->keys = array_grow(m->keys, m->len, &cap, sizeof({0})); m->values = array_grow(m->values, m->len, &m->cap, sizeof({1}));",
+                kc, vc
+            ).unwrap(),
+            None => write!(grow, "m->keys = array_grow(m->keys, m->len, &m->cap, sizeof({}));", kc).unwrap(),
+        }
+        grow.push_str(" } m->keys[m->len] = key;");
+        if value_c.is_some() {
+            grow.push_str(" m->values[m->len] = value;");
+        }
+        grow.push_str(" m->slots[at] = (HashSlot){ hash, (int64_t)m->len++ }; m->slots = hash_slots_resize(m->slots, &m->slot_count, m->len);");
+        grow
+    };
+    // remove: free the slot, then fill the entry's gap with the last entry
+    let unlink = |move_value: bool| {
+        format!(
+            "hash_slots_remove(m->slots, m->slot_count, at); int64_t last = (int64_t)m->len - 1; if (entry != last) {{ hash_slots_move(m->slots, m->slot_count, key_hash__{0}(m->keys[last]), last, entry); m->keys[entry] = m->keys[last];{1} }} m->len--;",
+            kc,
+            if move_value { " m->values[entry] = m->values[last];" } else { "" }
+        )
+    };
+
+    match value {
+        Some(value) => {
+            let vc = type_to_c(value);
+            let opt_v = type_to_c(&Type::Option(Box::new(value.clone())));
+            let arr_v = type_to_c(&Type::Array(Box::new(value.clone())));
+            writeln!(
+                out,
+                "void {0}__insert({0} *self, {1} key, {2} value) {{ {3} if (m->slots[at].entry >= 0) {{ m->values[m->slots[at].entry] = value; return; }} {4} }}",
+                cname, kc, vc, lookup, append(Some(&vc))
+            ).unwrap();
+            writeln!(
+                out,
+                "{2} {0}__get({0} *self, {1} key) {{ {0} m = *self; int64_t entry = m->slots[{0}__find(m, key, key_hash__{1}(key))].entry; {2} r = {{ .is_some = entry >= 0 }}; if (r.is_some) r.value = m->values[entry]; return r; }}",
+                cname, kc, opt_v
+            ).unwrap();
+            writeln!(
+                out,
+                "{2} {0}__remove({0} *self, {1} key) {{ {3} int64_t entry = m->slots[at].entry; {2} r = {{ .is_some = entry >= 0 }}; if (entry < 0) return r; r.value = m->values[entry]; {4} return r; }}",
+                cname, kc, opt_v, lookup, unlink(true)
+            ).unwrap();
+            writeln!(out, "{1} {0}__keys({0} *self) {{ return {1}__from((*self)->keys, (*self)->len); }}", cname, arr_k).unwrap();
+            writeln!(out, "{1} {0}__values({0} *self) {{ return {1}__from((*self)->values, (*self)->len); }}", cname, arr_v).unwrap();
+        }
+        None => {
+            writeln!(
+                out,
+                "bool {0}__insert({0} *self, {1} key) {{ {2} if (m->slots[at].entry >= 0) return false; {3} return true; }}",
+                cname, kc, lookup, append(None)
+            ).unwrap();
+            writeln!(
+                out,
+                "bool {0}__remove({0} *self, {1} key) {{ {2} int64_t entry = m->slots[at].entry; if (entry < 0) return false; {3} return true; }}",
+                cname, kc, lookup, unlink(false)
+            ).unwrap();
+            writeln!(out, "{1} {0}__to_array({0} *self) {{ return {1}__from((*self)->keys, (*self)->len); }}", cname, arr_k).unwrap();
+        }
+    }
+    out.push('\n');
+}
+
+// Array_T is a pointer to arena storage like Map_K_V, so copies see
+// each other's pushes and element changes. The elements grow in the arena
+fn emit_array_helpers(out: &mut String, cname: &str, elem: &Type) {
+    let ec = type_to_c(elem);
+    writeln!(
+        out,
+        "{0} {0}__new(void) {{ {0} self = arena_alloc(sizeof(struct {0})); *self = (struct {0}){{ 0 }}; return self; }}",
+        cname
+    ).unwrap();
+    writeln!(
+        out,
+        "{1} *{0}__at({0} arr, int64_t index) {{ if (index < 0 || (size_t)index >= arr->len) array_out_of_bounds(index, arr->len); return &arr->data[index]; }}",
+        cname, ec
+    ).unwrap();
+    writeln!(out, "int64_t {0}__len({0} *self) {{ return (int64_t)(*self)->len; }}", cname).unwrap();
+    writeln!(out, "bool {0}__is_empty({0} *self) {{ return (*self)->len == 0; }}", cname).unwrap();
+    writeln!(
+        out,
+        "void {0}__push({0} *self, {1} value) {{ {0} arr = *self; arr->data = array_grow(arr->data, arr->len, &arr->cap, sizeof({1})); arr->data[arr->len++] = value; }}",
+        cname, ec
+    ).unwrap();
+    writeln!(
+        out,
+        "{0} {0}__from(const {1} *elems, size_t len) {{ {0} arr = {0}__new(); arr->data = arena_alloc(len * sizeof({1})); arr->len = arr->cap = len; memcpy(arr->data, elems, len * sizeof({1})); return arr; }}",
+        cname, ec
+    ).unwrap();
+    out.push('\n');
+}
+
+fn emit_type_def(
+    out: &mut String,
+    ty: &Type,
+    structs: &HashMap<&str, &Stmt>,
+    done: &mut HashSet<String>,
+) {
+    let cname = type_to_c(ty);
+    match ty {
+        Type::Custom(name) => {
+            let Some(Stmt::Struct { fields, docs, field_docs, .. }) = structs.get(name.as_str()) else { return };
+            if !done.insert(cname) {
+                return;
+            }
+            for (_, ftype) in fields.iter() {
+                emit_type_def(out, ftype, structs, done);
+            }
+
+            emit_docs(out, docs, 0);
+            writeln!(out, "struct {} {{", name).unwrap();
+            for ((fname, ftype), fdocs) in fields.iter().zip(field_docs) {
+                emit_docs(out, fdocs, 1);
                 let cty = type_to_c(ftype);
-                write!(out, "    {} {};\n", cty, fname).unwrap();
+                writeln!(out, "    {} {};", cty, fname).unwrap();
+            }
+            out.push_str("};\n\n");
+        }
+
+        Type::Option(inner) => {
+            if !done.insert(cname.clone()) {
+                return;
+            }
+            emit_type_def(out, inner, structs, done);
+            emit_option(out, &cname, inner);
+        }
+
+        Type::Result(ok, err) => {
+            if !done.insert(cname.clone()) {
+                return;
+            }
+            emit_type_def(out, ok, structs, done);
+            emit_type_def(out, err, structs, done);
+            emit_result(out, &cname, ok, err);
+        }
+
+        Type::Array(elem) => {
+            if !done.insert(cname.clone()) {
+                return;
+            }
+            // only a pointer to the elements, a struct declared later is fine
+            if !matches!(**elem, Type::Custom(_)) {
+                emit_type_def(out, elem, structs, done);
+            }
+            writeln!(out, "typedef struct {0} *{0};", cname).unwrap();
+            writeln!(out, "struct {} {{", cname).unwrap();
+            writeln!(out, "    {} *data;", type_to_c(elem)).unwrap();
+            out.push_str("    size_t len;\n    size_t cap;\n");
+            out.push_str("};\n\n");
+        }
+
+        Type::Map(..) | Type::Set(_) => {
+            if !done.insert(cname.clone()) {
+                return;
+            }
+            let (key, value) = match ty {
+                Type::Map(key, value) => (key, Some(value)),
+                Type::Set(elem) => (elem, None),
+                _ => unreachable!(),
+            };
+            // only pointers again, see the Array case
+            for inner in std::iter::once(key).chain(value) {
+                if !matches!(**inner, Type::Custom(_)) {
+                    emit_type_def(out, inner, structs, done);
+                }
+            }
+            writeln!(out, "typedef struct {0} *{0};", cname).unwrap();
+            writeln!(out, "struct {} {{", cname).unwrap();
+            writeln!(out, "    {} *keys;", type_to_c(key)).unwrap();
+            if let Some(value) = value {
+                writeln!(out, "    {} *values;", type_to_c(value)).unwrap();
+            }
+            out.push_str("    HashSlot *slots;\n    size_t len;\n    size_t cap;\n    size_t slot_count;\n");
+            out.push_str("};\n\n");
+        }
+
+        Type::Tuple(elems) => {
+            if !done.insert(cname.clone()) {
+                return;
+            }
+            for elem in elems {
+                emit_type_def(out, elem, structs, done);
+            }
+            writeln!(out, "typedef struct {} {{", cname).unwrap();
+            for (i, elem) in elems.iter().enumerate() {
+                writeln!(out, "    {} _{};", type_to_c(elem), i).unwrap();
+            }
+            write!(out, "}} {};\n\n", cname).unwrap();
+        }
+
+        _ => {}
+    }
+}
+
+fn emit_option(out: &mut String, cname: &str, inner: &Type) {
+    let has_value = *inner != Type::Nil;
+    let vc = type_to_c(inner);
+
+    writeln!(out, "typedef struct {} {{", cname).unwrap();
+    out.push_str("    bool is_some;\n");
+    if has_value {
+        writeln!(out, "    {} value;", vc).unwrap();
+    }
+    write!(out, "}} {};\n\n", cname).unwrap();
+
+    writeln!(out, "bool {0}__is_some({0}* self) {{ return self->is_some; }}", cname).unwrap();
+    writeln!(out, "bool {0}__is_none({0}* self) {{ return !self->is_some; }}", cname).unwrap();
+    if has_value {
+        writeln!(
+            out,
+            "{1} {0}__unwrap({0}* self) {{ if (!self->is_some) panic(string_from_literal(\"called unwrap() on none\")); return self->value; }}",
+            cname, vc
+        ).unwrap();
+        writeln!(
+            out,
+            "{1} {0}__expect({0}* self, String msg) {{ if (!self->is_some) panic(msg); return self->value; }}",
+            cname, vc
+        ).unwrap();
+        writeln!(
+            out,
+            "{1} {0}__unwrap_or({0}* self, {1} fallback) {{ return self->is_some ? self->value : fallback; }}",
+            cname, vc
+        ).unwrap();
+    } else {
+        writeln!(
+            out,
+            "void {0}__unwrap({0}* self) {{ if (!self->is_some) panic(string_from_literal(\"called unwrap() on none\")); }}",
+            cname
+        ).unwrap();
+        writeln!(out, "void {0}__expect({0}* self, String msg) {{ if (!self->is_some) panic(msg); }}", cname).unwrap();
+    }
+    out.push('\n');
+}
+
+fn emit_result(out: &mut String, cname: &str, ok: &Type, err: &Type) {
+    let has_value = *ok != Type::Nil;
+    let has_error = *err != Type::Nil;
+    let vc = type_to_c(ok);
+    let ec = type_to_c(err);
+
+    writeln!(out, "typedef struct {} {{", cname).unwrap();
+    out.push_str("    bool is_ok;\n");
+    if has_value {
+        writeln!(out, "    {} value;", vc).unwrap();
+    }
+    if has_error {
+        writeln!(out, "    {} error;", ec).unwrap();
+    }
+    write!(out, "}} {};\n\n", cname).unwrap();
+
+    writeln!(out, "bool {0}__is_ok({0}* self) {{ return self->is_ok; }}", cname).unwrap();
+    writeln!(out, "bool {0}__is_err({0}* self) {{ return !self->is_ok; }}", cname).unwrap();
+    let value_ret = if has_value { "return self->value;" } else { "" };
+    writeln!(
+        out,
+        "{1} {0}__unwrap({0}* self) {{ if (!self->is_ok) panic(string_from_literal(\"called unwrap() on err\")); {2} }}",
+        cname, vc, value_ret
+    ).unwrap();
+    writeln!(
+        out,
+        "{1} {0}__expect({0}* self, String msg) {{ if (!self->is_ok) panic(msg); {2} }}",
+        cname, vc, value_ret
+    ).unwrap();
+    if has_value {
+        writeln!(
+            out,
+            "{1} {0}__unwrap_or({0}* self, {1} fallback) {{ return self->is_ok ? self->value : fallback; }}",
+            cname, vc
+        ).unwrap();
+    }
+    let error_ret = if has_error { "return self->error;" } else { "" };
+    writeln!(
+        out,
+        "{1} {0}__unwrap_err({0}* self) {{ if (self->is_ok) panic(string_from_literal(\"called unwrap_err() on ok\")); {2} }}",
+        cname, ec, error_ret
+    ).unwrap();
+    out.push('\n');
+}
+
+// runtime functions that return an Option/Result or a runtime struct, they can
+// only be defined once emit_types() has generated those types
+fn emit_builtins(out: &mut String, prog: &Program) {
+    out.push_str(
+r#"// errno after a failed call on path, as a Butter IoError
+IoError io_error(String path) {
+    int err = errno;
+    String message = string_new();
+    string_push_string(&message, path);
+    string_push(&message, ": ");
+    string_push(&message, strerror(err));
+    return (IoError){ .kind = string_from_literal(io_error_kind(err)), .message = message };
+}
+
+"#,
+    );
+
+    for name in &prog.builtins {
+        if let Some(def) = math_def(name) {
+            out.push_str(&def);
+            out.push('\n');
+            continue;
+        }
+        let def = match name.as_str() {
+            "read_line" => {
+                "Option_String read_line(void) { Option_String r; r.is_some = file_read_line(stdin, &r.value); return r; }"
+            }
+            "read_all" => "String read_all(void) { return stdin_read_all(); }",
+            "parse_int" => {
+                "Result_int64_t_String parse_int(String s) { Result_int64_t_String r; r.is_ok = string_parse_int(s, &r.value, &r.error); return r; }"
+            }
+            "parse_bool" => {
+                "Result_bool_String parse_bool(String s) { Result_bool_String r; r.is_ok = string_parse_bool(s, &r.value, &r.error); return r; }"
+            }
+            "parse_float" => {
+                "Result_double_String parse_float(String s) { Result_double_String r; r.is_ok = string_parse_float(s, &r.value, &r.error); return r; }"
+            }
+            "env_get" => {
+                "Option_String env_get(String name) { Option_String r; const char *v = getenv(name.data); r.is_some = v != NULL; if (v) r.value = string_from_literal(v); return r; }"
+            }
+            "env_set" => {
+                "void env_set(String name, String value) { if (setenv(name.data, value.data, 1) != 0) { String msg = string_from_literal(\"env_set: invalid variable name \"); string_push_quoted(&msg, name); panic(msg); } }"
+            }
+            // flushes stdout, nothing else is cleaned up
+            "exit" => "void process_exit(int64_t code) { fflush(stdout); exit((int)code); }",
+            "fs_read" => {
+                "Result_String_IoError fs_read(String path) { Result_String_IoError r; r.is_ok = file_read_all(path, &r.value); if (!r.is_ok) r.error = io_error(path); return r; }"
+            }
+            "fs_write" => {
+                "Result_void_IoError fs_write(String path, String text) { Result_void_IoError r; r.is_ok = file_write(path, text, \"wb\"); if (!r.is_ok) r.error = io_error(path); return r; }"
+            }
+            "fs_append" => {
+                "Result_void_IoError fs_append(String path, String text) { Result_void_IoError r; r.is_ok = file_write(path, text, \"ab\"); if (!r.is_ok) r.error = io_error(path); return r; }"
+            }
+            "fs_exists" => "bool fs_exists(String path) { struct stat st; return stat(path.data, &st) == 0; }",
+            "fs_remove" => {
+                "Result_void_IoError fs_remove(String path) { Result_void_IoError r; r.is_ok = remove(path.data) == 0; if (!r.is_ok) r.error = io_error(path); return r; }"
+            }
+            "fs_lines" => {
+                "Result_LineReader_IoError fs_lines(String path) { Result_LineReader_IoError r; FILE *f = fopen(path.data, \"rb\"); r.is_ok = f != NULL; if (f) r.value.file = (int64_t)(intptr_t)f; else r.error = io_error(path); return r; }"
+            }
+            "fs_list_dir" => {
+                "Result_DirReader_IoError fs_list_dir(String path) { Result_DirReader_IoError r; DIR *d = opendir(path.data); r.is_ok = d != NULL; if (d) r.value.dir = (int64_t)(intptr_t)d; else r.error = io_error(path); return r; }"
+            }
+            // the file/directory is closed once everything is read, after that next() keeps returning none
+            "LineReader__next" => {
+                "Option_String LineReader__next(LineReader *self) { Option_String r; FILE *f = (FILE *)(intptr_t)self->file; r.is_some = f != NULL && file_read_line(f, &r.value); if (f && !r.is_some) { fclose(f); self->file = 0; } return r; }"
+            }
+            "DirReader__next" => {
+                "Option_String DirReader__next(DirReader *self) { Option_String r; DIR *d = (DIR *)(intptr_t)self->dir; r.is_some = d != NULL && dir_read_name(d, &r.value); if (d && !r.is_some) { closedir(d); self->dir = 0; } return r; }"
+            }
+            "String__push_str" => "void String__push_str(String *self, String tail) { string_push_string(self, tail); }",
+            "String__len" => "int64_t String__len(String *self) { return string_char_count(*self); }",
+            "String__is_empty" => "bool String__is_empty(String *self) { return self->len == 0; }",
+            "String__substring" => {
+                "String String__substring(String *self, int64_t start, int64_t end) { size_t from, to; string_char_range(*self, start, end, &from, &to); return string_from_bytes(self->data + from, to - from); }"
             }
-            write!(out, "}} {};\n\n", name).unwrap();
+            "String__find" => {
+                "Option_int64_t String__find(String *self, String needle) { Option_int64_t r; int64_t at = string_find_bytes(*self, needle, 0); r.is_some = at >= 0; if (r.is_some) r.value = string_char_count((String){ self->data, (size_t)at, (size_t)at }); return r; }"
+            }
+            "String__contains" => "bool String__contains(String *self, String needle) { return string_find_bytes(*self, needle, 0) >= 0; }",
+            "String__starts_with" => {
+                "bool String__starts_with(String *self, String prefix) { return prefix.len <= self->len && memcmp(self->data, prefix.data, prefix.len) == 0; }"
+            }
+            "String__ends_with" => {
+                "bool String__ends_with(String *self, String suffix) { return suffix.len <= self->len && memcmp(self->data + self->len - suffix.len, suffix.data, suffix.len) == 0; }"
+            }
+            "String__split" => {
+                r#"Array_String String__split(String *self, String sep) {
+    if (sep.len == 0) panic(string_from_literal("split() needs a separator that isn't empty"));
+    Array_String parts = Array_String__new();
+    size_t start = 0;
+    int64_t at;
+    while ((at = string_find_bytes(*self, sep, start)) >= 0) {
+        Array_String__push(&parts, string_from_bytes(self->data + start, (size_t)at - start));
+        start = (size_t)at + sep.len;
+    }
+    Array_String__push(&parts, string_from_bytes(self->data + start, self->len - start));
+    return parts;
+}"#
+            }
+            "String__trim" => {
+                "String String__trim(String *self) { size_t from = 0, to = self->len; while (from < to && isspace((unsigned char)self->data[from])) from++; while (to > from && isspace((unsigned char)self->data[to - 1])) to--; return string_from_bytes(self->data + from, to - from); }"
+            }
+            "String__replace" => {
+                r#"String String__replace(String *self, String from, String to) {
+    if (from.len == 0) panic(string_from_literal("replace() needs a pattern that isn't empty"));
+    String s = string_new();
+    size_t start = 0;
+    int64_t at;
+    while ((at = string_find_bytes(*self, from, start)) >= 0) {
+        string_push_bytes(&s, self->data + start, (size_t)at - start);
+        string_push_string(&s, to);
+        start = (size_t)at + from.len;
+    }
+    string_push_bytes(&s, self->data + start, self->len - start);
+    return s;
+}"#
+            }
+            "String__to_upper" => "String String__to_upper(String *self) { return string_map_chars(*self, char_to_upper); }",
+            "String__to_lower" => "String String__to_lower(String *self) { return string_map_chars(*self, char_to_lower); }",
+            "String__repeat" => {
+                "String String__repeat(String *self, int64_t n) { if (n < 0) panic(string_from_literal(\"repeat() count can't be negative\")); String s = string_new(); for (int64_t i = 0; i < n; i++) string_push_string(&s, *self); return s; }"
+            }
+            "String__chars" => {
+                "Array_Char String__chars(String *self) { Array_Char chars = Array_Char__new(); size_t pos = 0; while (pos < self->len) Array_Char__push(&chars, utf8_decode(*self, &pos)); return chars; }"
+            }
+            "String__bytes" => {
+                "Array_uint8_t String__bytes(String *self) { return self->len == 0 ? Array_uint8_t__new() : Array_uint8_t__from((const uint8_t *)self->data, self->len); }"
+            }
+            "Array_String__join" => {
+                "String Array_String__join(Array_String *self, String sep) { String s = string_new(); for (size_t i = 0; i < (*self)->len; i++) { if (i > 0) string_push_string(&s, sep); string_push_string(&s, (*self)->data[i]); } return s; }"
+            }
+            other => panic!("no C definition for builtin '{}'", other),
+        };
+        out.push_str(def);
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
+// the math module, math.sqrt is math_sqrt in C. The Float ones are GCC builtins, so libm's names (log,
+// round, ...) never get declared and can't clash with the program's own
+fn math_def(name: &str) -> Option<String> {
+    let def = match name {
+        "math_abs_i" => "int64_t math_abs_i(int64_t x) { return x < 0 ? (int64_t)(0 - (uint64_t)x) : x; }".to_string(),
+        "math_abs_u" => "uint64_t math_abs_u(uint64_t x) { return x; }".to_string(),
+        "math_abs_f" => "double math_abs_f(double x) { return __builtin_fabs(x); }".to_string(),
+        "math_gcd" => {
+            "int64_t math_gcd(int64_t a, int64_t b) { uint64_t x = a < 0 ? 0 - (uint64_t)a : (uint64_t)a, y = b < 0 ? 0 - (uint64_t)b : (uint64_t)b; while (y != 0) { uint64_t t = x % y; x = y; y = t; } return (int64_t)x; }".to_string()
+        }
+        _ => {
+            if let Some((func, kind)) = name.rsplit_once('_')
+                && let Some(cty) = match kind {
+                    "i" => Some("int64_t"),
+                    "u" => Some("uint64_t"),
+                    "f" => Some("double"),
+                    _ => None,
+                }
+            {
+                match func {
+                    "math_min" => format!("{cty} {name}({cty} a, {cty} b) {{ return a < b ? a : b; }}"),
+                    "math_max" => format!("{cty} {name}({cty} a, {cty} b) {{ return a > b ? a : b; }}"),
+                    "math_clamp" => format!(
+                        "{cty} {name}({cty} x, {cty} lo, {cty} hi) {{ if (lo > hi) panic(string_from_literal(\"math.clamp: low is greater than high\")); return x < lo ? lo : x > hi ? hi : x; }}"
+                    ),
+                    _ => return None,
+                }
+            } else if let Some((func, ty)) = name.rsplit_once('_')
+                && let Some(ty) = Type::from_name(ty)
+            {
+                int_arith_def(name, func, &ty)?
+            } else if let Some(func) = name.strip_prefix("math_") {
+                match func {
+                    "pow" | "atan2" | "hypot" => format!("double {name}(double a, double b) {{ return __builtin_{func}(a, b); }}"),
+                    _ => format!("double {name}(double x) {{ return __builtin_{func}(x); }}"),
+                }
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(def)
+}
+
+// math_checked_add_I8 and co, on top of GCC's overflow checking builtins
+fn int_arith_def(name: &str, func: &str, ty: &Type) -> Option<String> {
+    let (kind, op) = func.strip_prefix("math_")?.split_once('_')?;
+    let cty = type_to_c(ty);
+    let limit = cty.trim_end_matches("_t").to_uppercase();
+    let min = if ty.is_unsigned() { "0".to_string() } else { format!("{limit}_MIN") };
+    let max = format!("{limit}_MAX");
+    let def = match kind {
+        "checked" => format!(
+            "Option_{cty} {name}({cty} a, {cty} b) {{ Option_{cty} r; r.is_some = !__builtin_{op}_overflow(a, b, &r.value); return r; }}"
+        ),
+        "wrapping" => format!("{cty} {name}({cty} a, {cty} b) {{ {cty} r; __builtin_{op}_overflow(a, b, &r); return r; }}"),
+        "saturating" => {
+            // which way it overflowed: adding a negative or subtracting a
+            // positive goes below, so does multiplying by a different sign
+            let goes_below = match op {
+                "add" => "b < 0",
+                "sub" => "b > 0",
+                _ => "(a < 0) != (b < 0)",
+            };
+            format!(
+                "{cty} {name}({cty} a, {cty} b) {{ {cty} r; if (!__builtin_{op}_overflow(a, b, &r)) return r; return {goes_below} ? {min} : {max}; }}"
+            )
+        }
+        _ => return None,
+    };
+    Some(def)
+}
+
+// `owner` is the struct name for methods, which become `Owner__name(Owner* self, ...)`
+fn emit_function(out: &mut String, func: &Stmt, owner: Option<&str>) {
+    if let Stmt::Func { name, returntype, body, docs, .. } = func {
+        emit_docs(out, docs, 0);
+        emit_signature(out, func, owner);
+        out.push(' ');
+        if name == "main" && owner.is_none() && *returntype == Type::Int {
+            // like C's main, reaching the end means success
+            out.push_str("{\n");
+            for stmt in body {
+                emit_stmt(out, stmt, 1);
+            }
+            out.push_str("    return 0;\n}\n");
+        } else {
+            emit_block(out, body, 0);
+        }
+        out.push('\n');
+    }
+}
+
+// `int64_t add(int64_t a, int64_t b)`, shared by the prototype and the definition
+fn emit_signature(out: &mut String, func: &Stmt, owner: Option<&str>) {
+    if let Stmt::Func { name, params, returntype, .. } = func {
+        // `main` is the C entry point, so Butter's main is renamed
+        let name = if name == "main" && owner.is_none() { "flip_main" } else { name.as_str() };
+        let ret = type_to_c(returntype);
+        match owner {
+            Some(owner) => write!(out, "{} {}__{}({}* self", ret, owner, name, owner).unwrap(),
+            None => write!(out, "{} {}(", ret, name).unwrap(),
+        }
+        for (i, (pname, pty)) in params.iter().enumerate() {
+            if i > 0 || owner.is_some() {
+                out.push_str(", ");
+            }
+            let cty = type_to_c(pty);
+            write!(out, "{} {}", cty, pname).unwrap();
+        }
+        if params.is_empty() && owner.is_none() {
+            // `f()` in a prototype would mean "any arguments" in C
+            out.push_str("void");
+        }
+        out.push(')');
+    }
+}
+
+// the C entry point: sets up the runtime, then calls the Butter main (flip_main)
+// with the command line and passes its Int result on as the exit status
+fn emit_main(out: &mut String, prog: &Program) {
+    let main = prog.stmts.iter().find_map(|stmt| match stmt {
+        Stmt::Func { name, params, returntype, .. } if name == "main" => Some((params, returntype)),
+        _ => None,
+    });
+    let (takes_args, returns_code) = match main {
+        Some((params, returntype)) => (!params.is_empty(), *returntype == Type::Int),
+        None => (false, false),
+    };
+
+    out.push_str("\nint main(int argc, char **argv) {\n");
+    out.push_str("    arena_init(1024 * 1024 * 16);\n");
+    out.push_str("    __init_globals();\n");
+    if takes_args {
+        out.push_str("    Array_String args = Array_String__new();\n");
+        out.push_str("    for (int i = 0; i < argc; i++) Array_String__push(&args, string_from_literal(argv[i]));\n");
+    } else {
+        out.push_str("    (void)argc;\n    (void)argv;\n");
+    }
+    let call = if takes_args { "flip_main(args)" } else { "flip_main()" };
+    if returns_code {
+        writeln!(out, "    int code = (int){};", call).unwrap();
+    } else {
+        writeln!(out, "    {};", call).unwrap();
+        out.push_str("    int code = 0;\n");
+    }
+    out.push_str("    fflush(stdout);\n");
+    out.push_str("    arena_destroy();\n");
+    out.push_str("    return code;\n");
+    out.push_str("}\n");
+}
+
+// top level let/const. Literal values become static initializers,
+// everything else is computed by __init_globals() before flip_main runs
+fn emit_globals(out: &mut String, prog: &Program) {
+    for stmt in &prog.stmts {
+        let (name, valuetype, value) = match stmt {
+            Stmt::Let { name, valuetype, value, .. } => (name, valuetype, value.as_ref()),
+            Stmt::Const { name, valuetype, value } => (name, valuetype, Some(value)),
+            _ => continue,
+        };
+        let cty = type_to_c(valuetype.as_ref().expect("global type was not resolved by the checker"));
+        write!(out, "static {} {}", cty, name).unwrap();
+        if let Some(value) = value
+            && is_static_init(value)
+        {
+            out.push_str(" = ");
+            emit_expr(out, value);
+        }
+        out.push_str(";\n");
+    }
+    out.push('\n');
+}
+
+fn emit_global_init(out: &mut String, prog: &Program) {
+    out.push_str("void __init_globals(void) {\n");
+    for stmt in &prog.stmts {
+        let (name, value) = match stmt {
+            Stmt::Let { name, value: Some(value), .. } | Stmt::Const { name, value, .. } => (name, value),
+            _ => continue,
+        };
+        if !is_static_init(value) {
+            write!(out, "    {} = ", name).unwrap();
+            emit_expr(out, value);
+            out.push_str(";\n");
         }
     }
+    out.push_str("}\n");
+}
+
+// a C constant expression, allowed as the initializer of a static
+fn is_static_init(expr: &Expr) -> bool {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::Char(_) => true,
+        Expr::Prefix { op: PrefixOp::Neg, rhs } => is_static_init(rhs),
+        _ => false,
+    }
+}
+
+// `///` comments carried over as C comments, so the generated code stays readable
+fn emit_docs(out: &mut String, docs: &[String], level: usize) {
+    for line in docs {
+        // a trailing backslash would splice the next line into the comment
+        indent(out, level);
+        writeln!(out, "// {}", line.trim_end_matches('\\')).unwrap();
+    }
+}
+
+// one `{value:spec}` of an interpolation, appended to __interp
+fn emit_formatted(out: &mut String, value: &Expr, spec: &FormatSpec) {
+    let push = |out: &mut String, target: &str| {
+        match spec.precision {
+            Some(_) => write!(out, "string_push_fixed({}, (double)(", target).unwrap(),
+            None => write!(out, "string_push_value({}, ", target).unwrap(),
+        }
+        emit_expr(out, value);
+        match spec.precision {
+            Some(precision) => write!(out, "), {}); ", precision).unwrap(),
+            None => out.push_str("); "),
+        }
+    };
+
+    let Some(width) = spec.width else {
+        push(out, "&__interp");
+        return;
+    };
+    // format on its own first, then pad it to the width
+    out.push_str("{ String __field = string_new(); ");
+    push(out, "&__field");
+    let align = match spec.align {
+        Some(Align::Left) => '<',
+        Some(Align::Center) => '^',
+        _ => '>',
+    };
+    let fill = spec.fill.unwrap_or(' ') as u32;
+    write!(out, "string_push_padded(&__interp, __field, {}, '{}', {}, {}); }} ", width, align, fill, spec.zero).unwrap();
+}
+
+// the C function that appends a value of type ty to a String.
+// Nested strings and chars are quoted, like `Person { name: "Liam" }`
+fn formatter_name(ty: &Type) -> String {
+    match ty {
+        Type::String => "string_push_quoted".to_string(),
+        Type::Char => "string_push_quoted_char".to_string(),
+        Type::Func(..) => "string_push_closure".to_string(),
+        Type::Custom(_) | Type::Array(_) | Type::Option(_) | Type::Result(..) | Type::Tuple(_) | Type::Map(..) | Type::Set(_) => {
+            format!("string_push__{}", type_to_c(ty))
+        }
+        _ => "string_push_value".to_string(),
+    }
+}
+
+// one formatter per printed struct/array/Option/Result/tuple, prototypes first since they call each other
+fn emit_formatters(out: &mut String, prog: &Program) {
+    // a struct with `fn to_string(self) => String` is shown through it
+    let mut has_to_string = HashSet::new();
+    for stmt in &prog.stmts {
+        if let Stmt::Impl { target, methods, .. } = stmt {
+            for method in methods {
+                if let Stmt::Func { name, params, returntype: Type::String, .. } = method
+                    && name == "to_string"
+                    && params.is_empty()
+                {
+                    has_to_string.insert(target.as_str());
+                }
+            }
+        }
+    }
+
+    for ty in &prog.display_types {
+        writeln!(out, "void {}(String *s, {} x);", formatter_name(ty), type_to_c(ty)).unwrap();
+    }
+    out.push('\n');
+
+    for ty in &prog.display_types {
+        writeln!(out, "void {}(String *s, {} x) {{", formatter_name(ty), type_to_c(ty)).unwrap();
+        match ty {
+            Type::Custom(name) if has_to_string.contains(name.as_str()) => {
+                writeln!(out, "    string_push_string(s, {}__to_string(&x));", name).unwrap();
+            }
+            Type::Custom(name) => {
+                let fields = prog.stmts.iter().find_map(|stmt| match stmt {
+                    Stmt::Struct { name: sname, fields, .. } if sname == name => Some(fields),
+                    _ => None,
+                });
+                let fields = fields.expect("printed struct was not declared");
+                if fields.is_empty() {
+                    writeln!(out, "    string_push(s, \"{} {{}}\");", name).unwrap();
+                } else {
+                    for (i, (fname, fty)) in fields.iter().enumerate() {
+                        let sep = if i == 0 { format!("{} {{ ", name) } else { ", ".to_string() };
+                        writeln!(out, "    string_push(s, \"{}{}: \");", sep, fname).unwrap();
+                        writeln!(out, "    {}(s, x.{});", formatter_name(fty), fname).unwrap();
+                    }
+                    out.push_str("    string_push(s, \" }\");\n");
+                }
+            }
+            Type::Array(elem) => {
+                out.push_str("    string_push(s, \"[\");\n");
+                out.push_str("    for (size_t i = 0; i < x->len; i++) {\n");
+                out.push_str("        if (i > 0) string_push(s, \", \");\n");
+                writeln!(out, "        {}(s, x->data[i]);", formatter_name(elem)).unwrap();
+                out.push_str("    }\n");
+                out.push_str("    string_push(s, \"]\");\n");
+            }
+            // {"a": 1, "b": 2} and {1, 2}, in entry order
+            Type::Map(..) | Type::Set(_) => {
+                out.push_str("    string_push(s, \"{\");\n");
+                out.push_str("    for (size_t i = 0; i < x->len; i++) {\n");
+                out.push_str("        if (i > 0) string_push(s, \", \");\n");
+                match ty {
+                    Type::Map(key, value) => {
+                        writeln!(out, "        {}(s, x->keys[i]);", formatter_name(key)).unwrap();
+                        out.push_str("        string_push(s, \": \");\n");
+                        writeln!(out, "        {}(s, x->values[i]);", formatter_name(value)).unwrap();
+                    }
+                    Type::Set(elem) => writeln!(out, "        {}(s, x->keys[i]);", formatter_name(elem)).unwrap(),
+                    _ => unreachable!(),
+                }
+                out.push_str("    }\n");
+                out.push_str("    string_push(s, \"}\");\n");
+            }
+            Type::Option(inner) => {
+                emit_variant_format(out, "x.is_some", "some", inner, "x.value");
+                out.push_str("    else string_push(s, \"none\");\n");
+            }
+            Type::Result(ok, err) => {
+                emit_variant_format(out, "x.is_ok", "ok", ok, "x.value");
+                emit_variant_format(out, "!x.is_ok", "err", err, "x.error");
+            }
+            Type::Tuple(elems) => {
+                out.push_str("    string_push(s, \"(\");\n");
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str("    string_push(s, \", \");\n");
+                    }
+                    writeln!(out, "    {}(s, x._{});", formatter_name(elem), i).unwrap();
+                }
+                out.push_str("    string_push(s, \")\");\n");
+            }
+            _ => unreachable!("no formatter for {}", ty),
+        }
+        out.push_str("}\n\n");
+    }
+}
+
+// `if (x.is_some) { string_push(s, "some("); ...; string_push(s, ")"); }`
+fn emit_variant_format(out: &mut String, cond: &str, tag: &str, payload: &Type, field: &str) {
+    if *payload == Type::Nil {
+        writeln!(out, "    if ({}) string_push(s, \"{}(nil)\");", cond, tag).unwrap();
+        return;
+    }
+    writeln!(out, "    if ({}) {{", cond).unwrap();
+    writeln!(out, "        string_push(s, \"{}(\");", tag).unwrap();
+    writeln!(out, "        {}(s, {});", formatter_name(payload), field).unwrap();
+    out.push_str("        string_push(s, \")\");\n");
+    out.push_str("    }\n");
+}
+
+// every function is declared up front, so definitions can come in any order
+fn emit_prototypes(out: &mut String, prog: &Program) {
+    for stmt in &prog.stmts {
+        match stmt {
+            Stmt::Func { .. } => {
+                emit_signature(out, stmt, None);
+                out.push_str(";\n");
+            }
+            Stmt::Impl { target, methods, .. } => {
+                for method in methods {
+                    emit_signature(out, method, Some(target));
+                    out.push_str(";\n");
+                }
+            }
+            _ => {}
+        }
+    }
+    out.push('\n');
 }
 
 fn emit_functions(out: &mut String, prog: &Program) {
     for stmt in &prog.stmts {
-        if let Stmt::Func { name, params, returntype, body } = stmt {
-            let is_main = name == "main";
-            if is_main {
-                out.push_str("void* flip_main(void)");
+        match stmt {
+            Stmt::Func { .. } => emit_function(out, stmt, None),
+            Stmt::Impl { target, methods, .. } => {
+                for method in methods {
+                    emit_function(out, method, Some(target));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn emit_closure_signature(out: &mut String, info: &ClosureInfo, params: &[(String, Type)]) {
+    write!(out, "{} {}(void* __env_ptr", type_to_c(&info.ret), info.name).unwrap();
+    for (pname, pty) in params {
+        write!(out, ", {} {}", type_to_c(pty), pname).unwrap();
+    }
+    out.push(')');
+}
+
+// environment structs and prototypes, so functions can create closures
+fn emit_closure_decls(out: &mut String, prog: &Program) {
+    for closure in &prog.closures {
+        if let Expr::Closure { params, info: Some(info), .. } = closure {
+            if !info.captures.is_empty() {
+                out.push_str("typedef struct {\n");
+                for (cname, cty) in &info.captures {
+                    writeln!(out, "    {} {};", type_to_c(cty), cname).unwrap();
+                }
+                writeln!(out, "}} {}_env;", info.name).unwrap();
+            }
+            emit_closure_signature(out, info, params);
+            out.push_str(";\n\n");
+        }
+    }
+}
+
+fn emit_closure_defs(out: &mut String, prog: &Program) {
+    for closure in &prog.closures {
+        if let Expr::Closure { params, body, info: Some(info) } = closure {
+            emit_closure_signature(out, info, params);
+            out.push_str(" {\n");
+            if info.captures.is_empty() {
+                out.push_str("    (void)__env_ptr;\n");
             } else {
-                let ret = type_to_c(returntype);
-                write!(out, "{} {}", ret, name).unwrap();
-                out.push('(');
-                for (i, (pname, pty)) in params.iter().enumerate() {
-                    if i > 0 {
-                        out.push_str(", ");
+                writeln!(out, "    {}_env* __env = __env_ptr;", info.name).unwrap();
+                for (cname, cty) in &info.captures {
+                    if cname == "self" {
+                        // methods see `self` through a pointer
+                        writeln!(out, "    {}* self = &__env->self;", type_to_c(cty)).unwrap();
+                    } else {
+                        writeln!(out, "    {} {} = __env->{};", type_to_c(cty), cname, cname).unwrap();
                     }
-                    let cty = type_to_c(pty);
-                    write!(out, "{} {}", cty, pname).unwrap();
                 }
-                out.push(')');
             }
-            out.push(' ');
-            emit_block(out, body, 0);
-            out.push('\n');
+            if info.ret == Type::Nil {
+                out.push_str("    ");
+            } else {
+                out.push_str("    return ");
+            }
+            emit_expr(out, body);
+            out.push_str(";\n}\n\n");
         }
     }
 }
@@ -399,6 +1726,10 @@ r#"#include <stdint.h>
 #include <stdio.h>
 #include <string.h>
 #include <stdlib.h>
+#include <ctype.h>
+#include <errno.h>
+#include <dirent.h>
+#include <sys/stat.h>
 
 typedef struct {
     unsigned char *base;
@@ -454,6 +1785,11 @@ typedef struct {
     size_t cap;
 } String;
 
+typedef struct {
+    void *fn;
+    void *env;
+} Closure;
+
 String string_new(void) {
     String s;
     s.len = 0;
@@ -480,118 +1816,523 @@ void string_grow(String *s, size_t extra) {
     }
 }
 
-void string_push(String *s, const char *suffix) {
-    size_t add = strlen(suffix);
-    string_grow(s, add);
+void string_push_bytes(String *s, const char *data, size_t len) {
+    string_grow(s, len);
 
-    memcpy(s->data + s->len, suffix, add);
-    s->len += add;
+    memcpy(s->data + s->len, data, len);
+    s->len += len;
     s->data[s->len] = '\0';
 }
 
-String string_from_literal(const char *lit) {
-    size_t len = strlen(lit);
+void string_push(String *s, const char *suffix) {
+    string_push_bytes(s, suffix, strlen(suffix));
+}
 
+// literals carry their length, a Butter string may hold NUL bytes
+String string_from_bytes(const char *data, size_t len) {
     String s;
     s.len = len;
     s.cap = len;
     s.data = (char *)arena_alloc(s.cap + 1);
-    memcpy(s.data, lit, len + 1);
+    memcpy(s.data, data, len);
+    s.data[len] = '\0';
 
     return s;
 }
 
-typedef struct {
-    void **data;
-    size_t len;
-    size_t cap;
-} Array_int64_t;
+String string_from_literal(const char *lit) {
+    return string_from_bytes(lit, strlen(lit));
+}
 
-typedef Array_int64_t Array_double;
-typedef Array_int64_t Array_String;
-typedef Array_int64_t Array_bool;
+// a Unicode scalar value, its own C type so _Generic can tell it from U32/U64
+typedef unsigned long long Char;
+
+// reads the character starting at byte *pos and moves *pos past it,
+// malformed bytes decode to U+FFFD one byte at a time
+Char utf8_decode(String s, size_t *pos) {
+    const unsigned char *p = (const unsigned char *)s.data + *pos;
+    size_t left = s.len - *pos;
+    Char c;
+    size_t n;
+
+    if (p[0] < 0x80) { c = p[0]; n = 1; }
+    else if ((p[0] & 0xE0) == 0xC0) { c = p[0] & 0x1F; n = 2; }
+    else if ((p[0] & 0xF0) == 0xE0) { c = p[0] & 0x0F; n = 3; }
+    else if ((p[0] & 0xF8) == 0xF0) { c = p[0] & 0x07; n = 4; }
+    else { *pos += 1; return 0xFFFD; }
+
+    if (n > left) { *pos += 1; return 0xFFFD; }
+    for (size_t i = 1; i < n; i++) {
+        if ((p[i] & 0xC0) != 0x80) { *pos += 1; return 0xFFFD; }
+        c = (c << 6) | (p[i] & 0x3F);
+    }
+    *pos += n;
+    return c;
+}
 
-Array_int64_t array_new(void) {
-    Array_int64_t arr;
-    arr.len = 0;
-    arr.cap = 8;
-    arr.data = (void **)arena_alloc(arr.cap * sizeof(void *));
-    return arr;
+// writes c as UTF-8 into out (4 bytes max) and returns the byte count
+size_t utf8_encode(Char c, char *out) {
+    if (c < 0x80) { out[0] = (char)c; return 1; }
+    if (c < 0x800) {
+        out[0] = (char)(0xC0 | (c >> 6));
+        out[1] = (char)(0x80 | (c & 0x3F));
+        return 2;
+    }
+    if (c < 0x10000) {
+        out[0] = (char)(0xE0 | (c >> 12));
+        out[1] = (char)(0x80 | ((c >> 6) & 0x3F));
+        out[2] = (char)(0x80 | (c & 0x3F));
+        return 3;
+    }
+    out[0] = (char)(0xF0 | (c >> 18));
+    out[1] = (char)(0x80 | ((c >> 12) & 0x3F));
+    out[2] = (char)(0x80 | ((c >> 6) & 0x3F));
+    out[3] = (char)(0x80 | (c & 0x3F));
+    return 4;
 }
 
-void array_grow(Array_int64_t *arr, size_t extra) {
-    size_t needed = arr->len + extra;
-    if (needed > arr->cap) {
-        size_t cap = arr->cap;
-        if (cap < 8) cap = 8;
-        while (cap < needed) {
-            cap *= 2;
-        }
+void string_push_char(String *s, Char c) {
+    char buf[5];
+    buf[utf8_encode(c, buf)] = '\0';
+    string_push(s, buf);
+}
+
+String string_from_char(Char c) {
+    String s = string_new();
+    string_push_char(&s, c);
+    return s;
+}
+
+// number of characters, not bytes
+int64_t string_char_count(String s) {
+    int64_t count = 0;
+    size_t pos = 0;
+    while (pos < s.len) {
+        utf8_decode(s, &pos);
+        count++;
+    }
+    return count;
+}
 
-        void **new_data = (void **)arena_alloc(cap * sizeof(void *));
-        memcpy(new_data, arr->data, arr->len * sizeof(void *));
+// the shortest text that reads back as the same value, 0.1 rather than 0.100000 and
+// 3.141592653589793 rather than 3.141593. `digits` is 17 for a double and 9 for a float.
+// Very large and very small values get an exponent (1e300, 1e-6), the rest always show a '.' (1.0).
+// The checker's format_float() folds constants the same way
+void format_double(char *buf, size_t size, double x, int digits) {
+    if (x != x) { snprintf(buf, size, "nan"); return; }
+    if (x - x != 0) { snprintf(buf, size, x < 0 ? "-inf" : "inf"); return; }
+    int prec = 0;
+    for (; prec < digits - 1; prec++) {
+        snprintf(buf, size, "%.*e", prec, x);
+        double back = strtod(buf, NULL);
+        if (digits < 17 ? (float)back == (float)x : back == x) break;
+    }
+    snprintf(buf, size, "%.*e", prec, x);
+    char *e = strchr(buf, 'e');
+    int exp = atoi(e + 1);
+    // printf writes 1e-06 and 1e+21, drop the '+' and the padding zero
+    if (exp < -5 || exp >= 17) { snprintf(e, size - (e - buf), "e%d", exp); return; }
+    snprintf(buf, size, "%.*f", prec - exp > 1 ? prec - exp : 1, x);
+}
 
-        arr->data = new_data;
-        arr->cap = cap;
+// the pieces of "Hello {name}", formatted the same way print() does
+void string_push_int(String *s, int64_t x) { char buf[32]; snprintf(buf, sizeof buf, "%lld", (long long)x); string_push(s, buf); }
+void string_push_uint(String *s, uint64_t x) { char buf[32]; snprintf(buf, sizeof buf, "%llu", (unsigned long long)x); string_push(s, buf); }
+void string_push_double(String *s, double x) { char buf[64]; format_double(buf, sizeof buf, x, 17); string_push(s, buf); }
+void string_push_float(String *s, float x) { char buf[64]; format_double(buf, sizeof buf, x, 9); string_push(s, buf); }
+void string_push_bool(String *s, bool x) { string_push(s, x ? "true" : "false"); }
+void string_push_string(String *s, String x) { string_push_bytes(s, x.data, x.len); }
+void string_push_fixed(String *s, double x, int prec) { char buf[512]; snprintf(buf, sizeof buf, "%.*f", prec, x); string_push(s, buf); }
+
+// "{:>8}": pads x with fill up to width characters, align is '<', '>' or '^'.
+// Zero padding goes between the sign and the digits
+void string_push_padded(String *s, String x, int64_t width, char align, Char fill, bool zero) {
+    int64_t pad = width - string_char_count(x);
+    if (pad <= 0) {
+        string_push_string(s, x);
+        return;
+    }
+    if (zero) {
+        size_t start = (x.len > 0 && (x.data[0] == '-' || x.data[0] == '+')) ? 1 : 0;
+        string_push_bytes(s, x.data, start);
+        for (int64_t i = 0; i < pad; i++) string_push(s, "0");
+        string_push_bytes(s, x.data + start, x.len - start);
+        return;
+    }
+    int64_t before = align == '<' ? 0 : align == '>' ? pad : pad / 2;
+    for (int64_t i = 0; i < before; i++) string_push_char(s, fill);
+    string_push_string(s, x);
+    for (int64_t i = before; i < pad; i++) string_push_char(s, fill);
+}
+
+// strings and chars inside a printed struct, array, ... are quoted
+void string_push_quoted(String *s, String x) {
+    string_push(s, "\"");
+    for (size_t i = 0; i < x.len; i++) {
+        switch (x.data[i]) {
+            case '"': string_push(s, "\\\""); break;
+            case '\\': string_push(s, "\\\\"); break;
+            case '\n': string_push(s, "\\n"); break;
+            case '\t': string_push(s, "\\t"); break;
+            default: string_push_bytes(s, x.data + i, 1);
+        }
     }
+    string_push(s, "\"");
+}
+void string_push_quoted_char(String *s, Char c) { string_push(s, "'"); string_push_char(s, c); string_push(s, "'"); }
+void string_push_closure(String *s, Closure c) { (void)c; string_push(s, "<fn>"); }
+
+#define string_push_value(s, x) _Generic((x), \
+    int8_t: string_push_int,             \
+    int16_t: string_push_int,            \
+    int32_t: string_push_int,            \
+    int64_t: string_push_int,            \
+    uint8_t: string_push_uint,           \
+    uint16_t: string_push_uint,          \
+    uint32_t: string_push_uint,          \
+    uint64_t: string_push_uint,          \
+    float: string_push_float,        \
+    double: string_push_double,      \
+    bool: string_push_bool,          \
+    Char: string_push_char,          \
+    String: string_push_string       \
+)(s, x)
+
+// element storage for every Array_T, see emit_array_helpers()
+void *array_grow(void *data, size_t len, size_t *cap, size_t elem_size) {
+    if (len < *cap) return data;
+    size_t new_cap = *cap < 8 ? 8 : *cap * 2;
+    void *new_data = arena_alloc(new_cap * elem_size);
+    if (len > 0) memcpy(new_data, data, len * elem_size);
+    *cap = new_cap;
+    return new_data;
+}
+
+// Map and Set keep their entries in insertion order in plain arrays. `slots`
+// is an open addressing table (linear probing, a power of two in size) of
+// entry indexes, -1 when free. The hash is kept next to the index, so the
+// table can be grown and cleaned up without knowing the key type.
+typedef struct HashSlot {
+    uint64_t hash;
+    int64_t entry;
+} HashSlot;
+
+uint64_t hash_int(uint64_t x) {
+    x ^= x >> 30; x *= 0xbf58476d1ce4e5b9ULL;
+    x ^= x >> 27; x *= 0x94d049bb133111ebULL;
+    return x ^ (x >> 31);
 }
 
-void array_push(Array_int64_t *arr, void *elem) {
-    array_grow(arr, 1);
-    arr->data[arr->len] = elem;
-    arr->len++;
+uint64_t hash_bytes(const char *data, size_t len) {
+    uint64_t h = 0xcbf29ce484222325ULL;
+    for (size_t i = 0; i < len; i++) h = (h ^ (uint8_t)data[i]) * 0x100000001b3ULL;
+    return hash_int(h);
 }
 
-void *array_get(Array_int64_t arr, int64_t index) {
-    if (index < 0 || (size_t)index >= arr.len) {
-        fprintf(stderr, "Array index out of bounds: %lld (len=%zu)\n", (long long)index, arr.len);
-        abort();
+uint64_t hash_combine(uint64_t h, uint64_t v) {
+    return h ^ (v + 0x9e3779b97f4a7c15ULL + (h << 6) + (h >> 2));
+}
+
+// a table big enough for `count` entries at 3/4 load, with the entries moved over
+HashSlot *hash_slots_resize(HashSlot *slots, size_t *slot_count, size_t count) {
+    size_t new_count = *slot_count < 8 ? 8 : *slot_count;
+    while (count * 4 > new_count * 3) new_count *= 2;
+    if (new_count == *slot_count) return slots;
+    HashSlot *fresh = arena_alloc(new_count * sizeof(HashSlot));
+    for (size_t i = 0; i < new_count; i++) fresh[i].entry = -1;
+    for (size_t i = 0; i < *slot_count; i++) {
+        if (slots[i].entry < 0) continue;
+        size_t at = slots[i].hash & (new_count - 1);
+        while (fresh[at].entry >= 0) at = (at + 1) & (new_count - 1);
+        fresh[at] = slots[i];
     }
-    return arr.data[index];
+    *slot_count = new_count;
+    return fresh;
 }
 
-void array_set(Array_int64_t *arr, int64_t index, void *value) {
-    if (index < 0 || (size_t)index >= arr->len) {
-        fprintf(stderr, "Array index out of bounds: %lld (len=%zu)\n", (long long)index, arr->len);
-        abort();
+// frees slot `at` and moves later slots of the same run back into the gap,
+// so every entry can still be reached from its hash without tombstones
+void hash_slots_remove(HashSlot *slots, size_t slot_count, size_t at) {
+    size_t mask = slot_count - 1;
+    size_t hole = at;
+    for (size_t i = (at + 1) & mask; slots[i].entry >= 0; i = (i + 1) & mask) {
+        size_t home = slots[i].hash & mask;
+        bool reachable = hole <= i ? (hole < home && home <= i) : (hole < home || home <= i);
+        if (!reachable) {
+            slots[hole] = slots[i];
+            hole = i;
+        }
     }
-    arr->data[index] = value;
+    slots[hole].entry = -1;
 }
 
-Array_int64_t array_from_literal(void **elems, size_t count) {
-    Array_int64_t arr;
-    arr.len = count;
-    arr.cap = count;
-    arr.data = (void **)arena_alloc(count * sizeof(void *));
-    memcpy(arr.data, elems, count * sizeof(void *));
-    return arr;
+// the entry at index `from` was moved to `to`
+void hash_slots_move(HashSlot *slots, size_t slot_count, uint64_t hash, int64_t from, int64_t to) {
+    size_t at = hash & (slot_count - 1);
+    while (slots[at].entry != from) at = (at + 1) & (slot_count - 1);
+    slots[at].entry = to;
+}
+
+void array_out_of_bounds(int64_t index, size_t len) {
+    fflush(stdout);
+    fprintf(stderr, "Array index out of bounds: %lld (len=%zu)\n", (long long)index, len);
+    abort();
 }
 
 #define print(x) _Generic((x), \
+    int8_t: print_int,             \
+    int16_t: print_int,            \
+    int32_t: print_int,            \
     int64_t: print_int,            \
+    uint8_t: print_uint,           \
+    uint16_t: print_uint,          \
+    uint32_t: print_uint,          \
+    uint64_t: print_uint,          \
+    float: print_float,        \
     double: print_double,      \
+    bool: print_bool,          \
+    Char: print_char,          \
     String: print_string       \
 )(x)
 
 #define println(x) _Generic((x), \
+    int8_t: println_int,             \
+    int16_t: println_int,            \
+    int32_t: println_int,            \
     int64_t: println_int,            \
+    uint8_t: println_uint,           \
+    uint16_t: println_uint,          \
+    uint32_t: println_uint,          \
+    uint64_t: println_uint,          \
+    float: println_float,        \
     double: println_double,      \
+    bool: println_bool,          \
+    Char: println_char,          \
     String: println_string       \
 )(x)
 
 void print_int(int64_t x) { printf("%lld", (long long)x); }
-void print_double(double x) { printf("%f", x); }
-void print_string(String s) { printf("%s", s.data); }
+void print_uint(uint64_t x) { printf("%llu", (unsigned long long)x); }
+void print_double(double x) { char buf[64]; format_double(buf, sizeof buf, x, 17); printf("%s", buf); }
+void print_float(float x) { char buf[64]; format_double(buf, sizeof buf, x, 9); printf("%s", buf); }
+void print_bool(bool x) { printf("%s", x ? "true" : "false"); }
+void print_string(String s) { fwrite(s.data, 1, s.len, stdout); }
+void print_char(Char c) { char buf[5]; buf[utf8_encode(c, buf)] = '\0'; printf("%s", buf); }
 
 void println_int(int64_t x) { printf("%lld\n", (long long)x); }
-void println_double(double x) { printf("%f\n", x); }
-void println_string(String s) { printf("%s\n", s.data); }
+void println_uint(uint64_t x) { printf("%llu\n", (unsigned long long)x); }
+void println_double(double x) { print_double(x); printf("\n"); }
+void println_float(float x) { print_float(x); printf("\n"); }
+void eprint(String s) { fflush(stdout); fwrite(s.data, 1, s.len, stderr); }
+void eprintln(String s) { eprint(s); fprintf(stderr, "\n"); }
+
+void println_bool(bool x) { printf("%s\n", x ? "true" : "false"); }
+void println_string(String s) { fwrite(s.data, 1, s.len, stdout); printf("\n"); }
+void println_char(Char c) { print_char(c); printf("\n"); }
+
+// one line without its "\n" / "\r\n", false at end of input
+bool file_read_line(FILE *f, String *line) {
+    *line = string_new();
+    int c;
+    bool any = false;
+    while ((c = getc(f)) != EOF) {
+        any = true;
+        if (c == '\n') break;
+        char b = (char)c;
+        string_push_bytes(line, &b, 1);
+    }
+    if (line->len > 0 && line->data[line->len - 1] == '\r') {
+        line->data[--line->len] = '\0';
+    }
+    return any;
+}
+
+String stdin_read_all(void) {
+    String s = string_new();
+    char buf[4096];
+    size_t n;
+    while ((n = fread(buf, 1, sizeof buf, stdin)) > 0) {
+        string_push_bytes(&s, buf, n);
+    }
+    return s;
+}
+
+// the fs_ helpers return false and leave errno set when they fail
+bool file_read_all(String path, String *out) {
+    FILE *f = fopen(path.data, "rb");
+    if (!f) return false;
+    *out = string_new();
+    char buf[4096];
+    size_t n;
+    while ((n = fread(buf, 1, sizeof buf, f)) > 0) {
+        string_push_bytes(out, buf, n);
+    }
+    bool ok = !ferror(f);
+    int err = errno;
+    fclose(f);
+    errno = err;
+    return ok;
+}
+
+bool file_write(String path, String text, const char *mode) {
+    FILE *f = fopen(path.data, mode);
+    if (!f) return false;
+    bool ok = fwrite(text.data, 1, text.len, f) == text.len;
+    int err = errno;
+    if (fclose(f) != 0 && ok) return false;
+    errno = err;
+    return ok;
+}
+
+// next entry name, skipping "." and ".."
+bool dir_read_name(DIR *d, String *name) {
+    struct dirent *entry;
+    while ((entry = readdir(d)) != NULL) {
+        if (strcmp(entry->d_name, ".") != 0 && strcmp(entry->d_name, "..") != 0) {
+            *name = string_from_literal(entry->d_name);
+            return true;
+        }
+    }
+    return false;
+}
+
+const char *io_error_kind(int err) {
+    switch (err) {
+        case ENOENT: return "not_found";
+        case EACCES: case EPERM: return "permission_denied";
+        case EEXIST: return "already_exists";
+        case EISDIR: return "is_directory";
+        case ENOTDIR: return "not_directory";
+        case ENOTEMPTY: return "not_empty";
+        default: return "other";
+    }
+}
+
+// parse_int/parse_float: the whole string has to be the number, no spaces around it
+bool string_parse_int(String s, int64_t *out, String *error) {
+    char *end;
+    errno = 0;
+    long long v = strtoll(s.data, &end, 10);
+    bool starts_ok = s.len > 0 && (isdigit((unsigned char)s.data[0]) || s.data[0] == '-' || s.data[0] == '+');
+    if (!starts_ok || end == s.data || end != s.data + s.len) {
+        *error = string_new();
+        string_push(error, "invalid integer ");
+        string_push_quoted(error, s);
+        return false;
+    }
+    if (errno == ERANGE) {
+        *error = string_new();
+        string_push(error, "integer out of range ");
+        string_push_quoted(error, s);
+        return false;
+    }
+    *out = (int64_t)v;
+    return true;
+}
+
+bool string_parse_float(String s, double *out, String *error) {
+    char *end;
+    double v = strtod(s.data, &end);
+    bool starts_ok = s.len > 0 && !isspace((unsigned char)s.data[0]);
+    if (!starts_ok || end == s.data || end != s.data + s.len) {
+        *error = string_new();
+        string_push(error, "invalid float ");
+        string_push_quoted(error, s);
+        return false;
+    }
+    *out = v;
+    return true;
+}
+
+bool string_parse_bool(String s, bool *out, String *error) {
+    if (s.len == 4 && memcmp(s.data, "true", 4) == 0) { *out = true; return true; }
+    if (s.len == 5 && memcmp(s.data, "false", 5) == 0) { *out = false; return true; }
+    *error = string_new();
+    string_push(error, "invalid bool ");
+    string_push_quoted(error, s);
+    return false;
+}
+
+// `x as I32` on a float: cut off the fraction, out of range values stop at
+// the type's limits and NaN becomes 0
+#define FLOAT_TO_INT(name, T, lo, hi) \
+    T name(double x) { if (x != x) return 0; if (x <= (double)(lo)) return lo; if (x >= (double)(hi)) return hi; return (T)x; }
+FLOAT_TO_INT(float_to_i8, int8_t, INT8_MIN, INT8_MAX)
+FLOAT_TO_INT(float_to_i16, int16_t, INT16_MIN, INT16_MAX)
+FLOAT_TO_INT(float_to_i32, int32_t, INT32_MIN, INT32_MAX)
+FLOAT_TO_INT(float_to_int, int64_t, INT64_MIN, INT64_MAX)
+FLOAT_TO_INT(float_to_u8, uint8_t, 0, UINT8_MAX)
+FLOAT_TO_INT(float_to_u16, uint16_t, 0, UINT16_MAX)
+FLOAT_TO_INT(float_to_u32, uint32_t, 0, UINT32_MAX)
+FLOAT_TO_INT(float_to_u64, uint64_t, 0, UINT64_MAX)
 
 void panic(String msg) {
+    fflush(stdout);
     fprintf(stderr, "Runtime panic: %s\n", msg.data);
     abort();
 }
 
+// byte offsets of the characters start..end, panics when they're outside the string
+void string_char_range(String s, int64_t start, int64_t end, size_t *from, size_t *to) {
+    int64_t count = string_char_count(s);
+    if (start < 0 || end < start || end > count) {
+        char buf[160];
+        snprintf(buf, sizeof buf, "substring %lld..%lld out of range for a string of %lld characters", (long long)start, (long long)end, (long long)count);
+        panic(string_from_literal(buf));
+    }
+    size_t pos = 0;
+    for (int64_t i = 0; i < start; i++) utf8_decode(s, &pos);
+    *from = pos;
+    for (int64_t i = start; i < end; i++) utf8_decode(s, &pos);
+    *to = pos;
+}
+
+// byte position of needle in s at or after byte from, -1 when it isn't there
+int64_t string_find_bytes(String s, String needle, size_t from) {
+    for (size_t i = from; i + needle.len <= s.len; i++) {
+        if (memcmp(s.data + i, needle.data, needle.len) == 0) return (int64_t)i;
+    }
+    return -1;
+}
+
+// case mapping for ASCII, Latin-1, Latin Extended-A, Greek and Cyrillic,
+// every other character is left as it is
+Char char_to_upper(Char c) {
+    if (c >= 'a' && c <= 'z') return c - 32;
+    if (c >= 0xE0 && c <= 0xFE && c != 0xF7) return c - 32;
+    if (c == 0xFF) return 0x178;
+    if (c >= 0x100 && c <= 0x137 && c % 2 == 1 && c != 0x131) return c - 1;
+    if (c >= 0x139 && c <= 0x148 && c % 2 == 0) return c - 1;
+    if (c >= 0x14A && c <= 0x177 && c % 2 == 1) return c - 1;
+    if (c >= 0x179 && c <= 0x17E && c % 2 == 0) return c - 1;
+    if (c == 0x3C2) return 0x3A3;
+    if (c >= 0x3B1 && c <= 0x3C9) return c - 32;
+    if (c >= 0x430 && c <= 0x44F) return c - 32;
+    if (c >= 0x450 && c <= 0x45F) return c - 80;
+    return c;
+}
+
+Char char_to_lower(Char c) {
+    if (c >= 'A' && c <= 'Z') return c + 32;
+    if (c >= 0xC0 && c <= 0xDE && c != 0xD7) return c + 32;
+    if (c == 0x178) return 0xFF;
+    if (c >= 0x100 && c <= 0x137 && c % 2 == 0 && c != 0x130) return c + 1;
+    if (c >= 0x139 && c <= 0x148 && c % 2 == 1) return c + 1;
+    if (c >= 0x14A && c <= 0x177 && c % 2 == 0) return c + 1;
+    if (c >= 0x179 && c <= 0x17E && c % 2 == 1) return c + 1;
+    if (c >= 0x391 && c <= 0x3A9 && c != 0x3A2) return c + 32;
+    if (c >= 0x410 && c <= 0x42F) return c + 32;
+    if (c >= 0x400 && c <= 0x40F) return c + 80;
+    return c;
+}
+
+String string_map_chars(String s, Char (*map)(Char)) {
+    String out = string_new();
+    size_t pos = 0;
+    while (pos < s.len) string_push_char(&out, map(utf8_decode(s, &pos)));
+    return out;
+}
+
 
 "#
     );
@@ -602,19 +2343,25 @@ pub fn transpile(program: Program, name: &str) {
 
     emit_prelude(&mut out);
 
-    emit_structs(&mut out, &program);
+    emit_types(&mut out, &program);
+
+    emit_builtins(&mut out, &program);
+
+    emit_globals(&mut out, &program);
+
+    emit_prototypes(&mut out, &program);
+
+    emit_formatters(&mut out, &program);
+
+    emit_closure_decls(&mut out, &program);
 
     emit_functions(&mut out, &program);
 
-    out.push_str(
-r#"
-int main(void) {
-    arena_init(1024 * 1024 * 16);
-    flip_main();
-    arena_destroy();
-    return 0;
-}
-"#
-    );
+    emit_closure_defs(&mut out, &program);
+
+    emit_global_init(&mut out, &program);
+
+    emit_main(&mut out, &program);
+
     fs::write(format!("{name}.c"), out).expect("Failed to write C file");
 }
@@ -0,0 +1,607 @@
+// End-to-end tests: every program in tests/examples is compiled with the butter
+// binary, run, and its stdout compared with the .out file next to it.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const BUTTER: &str = env!("CARGO_BIN_EXE_butter");
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("examples")
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Compiles `source` as <name>.bt in a fresh directory and returns the compiler's output
+// along with the path the binary is written to.
+fn compile(name: &str, source: &str) -> (Output, PathBuf) {
+    let dir = work_dir(name);
+    let file = dir.join(format!("{}.bt", name));
+    fs::write(&file, source).unwrap();
+    let output = Command::new(BUTTER)
+        .arg("build")
+        .arg(&file)
+        .output()
+        .unwrap();
+    (output, dir.join(name))
+}
+
+fn compile_example(name: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/examples")
+        .join(format!("{}.bt", name));
+    let source = fs::read_to_string(&path).unwrap();
+    let (output, binary) = compile(name, &source);
+    // gcc errors don't change the compiler's exit status, so look for the binary too
+    assert!(
+        output.status.success() && binary.exists(),
+        "{} did not compile:\n{}",
+        name,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    binary
+}
+
+// Runs the binary from an empty directory, so examples that touch files start clean.
+fn run(binary: &Path, args: &[&str], env: &[(&str, &str)], stdin: &str) -> Output {
+    let dir = binary.parent().unwrap().join("run");
+    fs::create_dir_all(&dir).unwrap();
+    let mut child = Command::new(binary)
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn expected(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/examples")
+        .join(format!("{}.out", name));
+    fs::read_to_string(path).unwrap()
+}
+
+fn check_with(name: &str, args: &[&str], env: &[(&str, &str)], stdin: &str, code: i32) {
+    let binary = compile_example(name);
+    let output = run(&binary, args, env, stdin);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        expected(name),
+        "stdout of {}",
+        name
+    );
+    assert_eq!(output.status.code(), Some(code), "exit code of {}", name);
+}
+
+fn check(name: &str) {
+    check_with(name, &[], &[], "", 0);
+}
+
+// Compiles a program that must be rejected and checks the message it's rejected with.
+fn check_error(name: &str, source: &str, message: &str) {
+    let (output, binary) = compile(name, source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !output.status.success(),
+        "{} compiled but should not have",
+        name
+    );
+    assert!(!binary.exists(), "{} produced a binary", name);
+    assert!(
+        stderr.contains("[BUTTER COMPILER ERROR]"),
+        "{}: {}",
+        name,
+        stderr
+    );
+    assert!(
+        stderr.contains(message),
+        "{}: expected '{}' in:\n{}",
+        name,
+        message,
+        stderr
+    );
+    assert!(
+        !stderr.contains("panicked"),
+        "{} panicked:\n{}",
+        name,
+        stderr
+    );
+}
+
+#[test]
+fn impl_blocks() {
+    check("impl_blocks");
+}
+
+#[test]
+fn error_assign_through_self() {
+    check_error(
+        "error_assign_through_self",
+        "struct P {\n    x: Int\n}\n\nimpl P {\n    fn bump(self) => nil {\n        self.x += 1;\n    }\n}\n\nfn main() => nil {\n}\n",
+        "cannot assign through 'self' in a method that takes 'self'",
+    );
+}
+
+#[test]
+fn error_mut_method_on_immutable_binding() {
+    check_error(
+        "error_mut_method_on_immutable_binding",
+        "struct P {\n    x: Int\n}\n\nimpl P {\n    fn bump(mut self) => nil {\n        self.x += 1;\n    }\n}\n\nfn main() => nil {\n    let p = P { x = 1 };\n    p.bump();\n    println(p.x);\n}\n",
+        "cannot call 'mut self' method 'bump' on 'p', declare it with 'let mut'",
+    );
+}
+
+#[test]
+fn error_assign_field_of_immutable_binding() {
+    check_error(
+        "error_assign_field_of_immutable_binding",
+        "struct P {\n    x: Int\n}\n\nfn main() => nil {\n    let p = P { x = 1 };\n    p.x = 2;\n}\n",
+        "cannot assign to 'p', declare it with 'let mut'",
+    );
+}
+
+#[test]
+fn traits() {
+    check("traits");
+}
+
+#[test]
+fn error_trait_method_clashes_with_inherent() {
+    check_error(
+        "error_trait_method_clashes_with_inherent",
+        "trait Show {\n    fn show(self) => String;\n}\n\nstruct P {\n    x: Int\n}\n\nimpl Show for P {\n    fn show(self) => String {\n        return \"trait\";\n    }\n}\n\nimpl P {\n    fn show(self) => String {\n        return \"inherent\";\n    }\n}\n\nfn main() => nil {\n}\n",
+        "method 'show' of trait 'Show' clashes with another method 'show' on struct 'P'",
+    );
+}
+
+#[test]
+fn option_result() {
+    check("option_result");
+}
+
+#[test]
+fn error_try_outside_option_function() {
+    check_error(
+        "error_try_outside_option_function",
+        "fn f() => Int {\n    let x: Int = some(1)?;\n    return x;\n}\n\nfn main() => nil {\n}\n",
+        "'?' on an Option can only be used in a function that returns an Option",
+    );
+}
+
+#[test]
+fn error_mismatched_let() {
+    check_error(
+        "error_mismatched_let",
+        "fn main() => nil {\n    let x: Int = \"a\";\n}\n",
+        "mismatched types in initializer of 'x': expected Int, found String",
+    );
+}
+
+#[test]
+fn error_mismatched_return() {
+    check_error(
+        "error_mismatched_return",
+        "fn f() => Int {\n    return true;\n}\n\nfn main() => nil {\n}\n",
+        "mismatched types in return value: expected Int, found Bool",
+    );
+}
+
+#[test]
+fn error_mismatched_field() {
+    check_error(
+        "error_mismatched_field",
+        "struct P {\n    x: Int\n}\n\nfn main() => nil {\n    let p: P = P { x = 1.5 };\n}\n",
+        "mismatched types in field 'P.x': expected Int, found Float",
+    );
+}
+
+#[test]
+fn unwrap_panic() {
+    let binary = compile_example("unwrap_panic");
+    let output = run(&binary, &[], &[], "");
+    // what was printed before the panic is not lost in stdout's buffer
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        expected("unwrap_panic")
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Runtime panic"));
+    assert!(!output.status.success());
+}
+
+#[test]
+fn closures() {
+    check("closures");
+}
+
+#[test]
+fn tuples() {
+    check("tuples");
+}
+
+#[test]
+fn inference() {
+    check("inference");
+}
+
+#[test]
+fn error_cannot_infer_none() {
+    check_error(
+        "error_cannot_infer_none",
+        "fn main() => nil {\n    let x = none;\n}\n",
+        "cannot infer the type of 'none', add a type annotation",
+    );
+}
+
+#[test]
+fn sized_numbers() {
+    check("sized_numbers");
+}
+
+#[test]
+fn error_literal_overflow() {
+    check_error(
+        "error_literal_overflow",
+        "fn main() => nil {\n    let b: U8 = 256;\n}\n",
+        "integer literal 256 does not fit in U8 (0..=255)",
+    );
+}
+
+#[test]
+fn chars() {
+    check("chars");
+}
+
+#[test]
+fn literals() {
+    check("literals");
+}
+
+#[test]
+fn error_float_literal_too_large() {
+    check_error(
+        "error_float_literal_too_large",
+        "fn main() => nil {\n    let f = 1e400;\n}\n",
+        "float literal '1e400' is too large to be represented",
+    );
+}
+
+#[test]
+fn bitwise() {
+    check("bitwise");
+}
+
+#[test]
+fn error_shift_too_large() {
+    check_error(
+        "error_shift_too_large",
+        "fn main() => nil {\n    let b: U8 = 1;\n    let c = b << 8;\n}\n",
+        "shift amount 8 is too large for U8 (8 bits)",
+    );
+}
+
+#[test]
+fn comments() {
+    check("comments");
+}
+
+#[test]
+fn error_unterminated_block_comment() {
+    check_error(
+        "error_unterminated_block_comment",
+        "fn main() => nil {\n}\n/* open /* nested */\n",
+        "Unterminated block comment",
+    );
+}
+
+#[test]
+fn interpolation() {
+    check("interpolation");
+}
+
+#[test]
+fn error_unmatched_brace() {
+    check_error(
+        "error_unmatched_brace",
+        "fn main() => nil {\n    println(\"a}b\");\n}\n",
+        "Unmatched '}' in string literal",
+    );
+}
+
+#[test]
+fn error_unterminated_brace() {
+    check_error(
+        "error_unterminated_brace",
+        "fn main() => nil {\n    println(\"{\\\"id\\\": 1}\");\n}\n",
+        "Unterminated '{' in string literal",
+    );
+}
+
+#[test]
+fn raw_strings() {
+    check("raw_strings");
+}
+
+#[test]
+fn error_unterminated_raw_string() {
+    check_error(
+        "error_unterminated_raw_string",
+        "fn main() => nil {\n    let s = r#\"abc\";\n}\n",
+        "Unterminated raw string literal",
+    );
+}
+
+#[test]
+fn globals() {
+    check("globals");
+}
+
+#[test]
+fn error_assign_to_const() {
+    check_error(
+        "error_assign_to_const",
+        "const LIMIT: Int = 10;\n\nfn main() => nil {\n    LIMIT = 5;\n}\n",
+        "cannot assign to const 'LIMIT'",
+    );
+}
+
+#[test]
+fn forward_decls() {
+    check("forward_decls");
+}
+
+#[test]
+fn printing() {
+    check("printing");
+}
+
+#[test]
+fn format_strings() {
+    let binary = compile_example("format_strings");
+    let output = run(&binary, &[], &[], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        expected("format_strings")
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "failed after 42 tries\n"
+    );
+}
+
+#[test]
+fn error_empty_hole_outside_print() {
+    check_error(
+        "error_empty_hole_outside_print",
+        "fn main() => nil {\n    let body = \"{}\";\n}\n",
+        "'{}' only gets a value in print/println/eprint/eprintln/format calls",
+    );
+}
+
+#[test]
+fn error_placeholder_count() {
+    check_error(
+        "error_placeholder_count",
+        "fn main() => nil {\n    println(\"{} {}\", 1);\n}\n",
+        "format string of println() has 2 placeholder(s) but 1 argument(s) were given",
+    );
+}
+
+#[test]
+fn input() {
+    check_with("input", &[], &[], "1\n2\r\nthree\n40", 0);
+}
+
+#[test]
+fn files() {
+    check("files");
+}
+
+#[test]
+fn arrays() {
+    check("arrays");
+}
+
+#[test]
+fn array_compat() {
+    check("array_compat");
+}
+
+#[test]
+fn args_env() {
+    check_with(
+        "args_env",
+        &["one", "two"],
+        &[("BUTTER_TEST", "set")],
+        "",
+        0,
+    );
+}
+
+#[test]
+fn args_env_without_args() {
+    let binary = compile_example("args_env");
+    let output = run(&binary, &[], &[], "");
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn early_exit() {
+    check_with("early_exit", &[], &[], "", 2);
+}
+
+#[test]
+fn exit_code() {
+    check_with("exit_code", &[], &[], "", 3);
+}
+
+#[test]
+fn error_main_signature() {
+    check_error(
+        "error_main_signature",
+        "fn main(x: Int) => nil {\n}\n",
+        "'main' takes no parameters or a single 'args: Array<String>'",
+    );
+}
+
+#[test]
+fn string_methods() {
+    check("string_methods");
+}
+
+#[test]
+fn substring_out_of_range_panics() {
+    let (output, binary) = compile(
+        "substring_out_of_range_panics",
+        "fn main() => nil {\n    println(\"abc\".substring(2, 5));\n}\n",
+    );
+    assert!(output.status.success());
+    let output = run(&binary, &[], &[], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("substring 2..5 out of range for a string of 3 characters"),
+        "{}",
+        stderr
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn conversions() {
+    check("conversions");
+}
+
+#[test]
+fn error_string_plus_int() {
+    check_error(
+        "error_string_plus_int",
+        "fn main() => nil {\n    let s = \"a\" + 5;\n}\n",
+        "cannot add String and Int, turn the other side into a String with to_string()",
+    );
+}
+
+#[test]
+fn math() {
+    check("math");
+}
+
+#[test]
+fn error_math_float_function_on_int() {
+    check_error(
+        "error_math_float_function_on_int",
+        "fn main() => nil {\n    let x = math.sqrt(2);\n}\n",
+        "mismatched types in argument to function 'math.sqrt': expected Float, found Int",
+    );
+}
+
+#[test]
+fn array_for() {
+    check("array_for");
+}
+
+#[test]
+fn collections() {
+    check("collections");
+}
+
+#[test]
+fn error_float_set_key() {
+    check_error(
+        "error_float_set_key",
+        "fn main() => nil {\n    let s: Set<Float> = set_new();\n}\n",
+        "Float can't be used in Set<Float>",
+    );
+}
+
+#[test]
+fn error_multi_char_literal() {
+    check_error(
+        "error_multi_char_literal",
+        "fn main() => nil {\n    let c = 'ab';\n}\n",
+        "Character literal holds more than one character",
+    );
+}
+
+#[test]
+fn error_empty_char_literal() {
+    check_error(
+        "error_empty_char_literal",
+        "fn main() => nil {\n    let c = '';\n}\n",
+        "Empty character literal",
+    );
+}
+
+#[test]
+fn error_unknown_escape() {
+    check_error(
+        "error_unknown_escape",
+        "fn main() => nil {\n    let s = \"\\q\";\n}\n",
+        "Unknown escape sequence: \\q",
+    );
+}
+
+#[test]
+fn error_invalid_unicode_escape() {
+    check_error(
+        "error_invalid_unicode_escape",
+        "fn main() => nil {\n    let c = '\\u{D800}';\n}\n",
+        "Invalid unicode escape: \\u{D800}",
+    );
+}
+
+#[test]
+fn error_unterminated_string() {
+    check_error(
+        "error_unterminated_string",
+        "fn main() => nil {\n    let s = \"abc;\n}\n",
+        "Unterminated string literal starting on line 2",
+    );
+}
+
+#[test]
+fn error_unexpected_character() {
+    check_error(
+        "error_unexpected_character",
+        "fn main() => nil {\n    let x = 1;\n    println(x @ 2);\n    println(\"{x $ 2}\");\n}\n",
+        "Unexpected character '@' on line 3",
+    );
+    check_error(
+        "error_unexpected_character_in_hole",
+        "fn main() => nil {\n    let x = 1;\n\n    println(\"{x $ 2}\");\n}\n",
+        "Unexpected character '$' on line 4",
+    );
+}
+
+#[test]
+fn error_unknown_math_function() {
+    check_error(
+        "error_unknown_math_function",
+        "fn main() => nil {\n    println(math.foo(1.0));\n}\n",
+        "the math module has no function 'foo'",
+    );
+}
+
+#[test]
+fn error_generic_math_value() {
+    check_error(
+        "error_generic_math_value",
+        "fn main() => nil {\n    let f = math.abs;\n}\n",
+        "math.abs works on any number type",
+    );
+}